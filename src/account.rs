@@ -0,0 +1,97 @@
+/// Account affected by an entry, written `[checking]` or, for a transfer
+/// between two accounts, `[checking>savings]` in database files.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+    serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub enum Account {
+    /// The value of the entry is added to the account.
+    One(String),
+    /// The value of the entry is moved from the first to the second account.
+    Transfer(String, String),
+}
+
+impl Account {
+    /// Parses the bracketed form used in database files.
+    pub fn from_token(s: &str) -> Result<Account, String> {
+        s.strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .ok_or_else(|| format!(r#"invalid account "{}""#, s))?
+            .parse()
+    }
+
+    fn valid_name(s: &str) -> bool {
+        !s.is_empty()
+            && !s.contains(|c: char|
+                c.is_whitespace() || "[]>".contains(c))
+    }
+}
+
+impl std::str::FromStr for Account {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ret = match s.find('>') {
+            Some(i) => Account::Transfer(
+                String::from(&s[..i]), String::from(&s[i + 1..])),
+            None => Account::One(String::from(s)),
+        };
+        let valid = match &ret {
+            Account::One(x) => Account::valid_name(x),
+            Account::Transfer(f, t) =>
+                Account::valid_name(f) && Account::valid_name(t) && f != t,
+        };
+        if valid {
+            Ok(ret)
+        } else {
+            Err(format!(r#"invalid account "{}""#, s))
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for Account {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Account> for String {
+    fn from(a: Account) -> String {
+        a.to_string()
+    }
+}
+
+impl std::fmt::Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Account::One(x) => f.write_str(x),
+            Account::Transfer(from, to) => write!(f, "{}>{}", from, to),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Account;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Account::from_token("[checking]"),
+            Ok(Account::One(String::from("checking"))));
+        assert_eq!(
+            Account::from_token("[checking>savings]"),
+            Ok(Account::Transfer(
+                String::from("checking"), String::from("savings"))));
+        for x in &[
+            "checking", "[]", "[a>]", "[>b]", "[a>b>c]", "[a>a]", "[a b]",
+        ] {
+            assert!(Account::from_token(x).is_err(), "{}", x);
+        }
+        let a = Account::from_token("[checking>savings]").unwrap();
+        assert_eq!(a.to_string(), "checking>savings");
+    }
+}
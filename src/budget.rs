@@ -0,0 +1,323 @@
+use std::io::Write;
+
+use chrono::Datelike;
+
+use super::commodity::Commodity;
+use super::db;
+use super::dec;
+use super::money::{self, Money};
+use super::tag::Tag;
+
+/// Name of the file in the database directory which contains the budgets.
+pub const FILE_NAME: &str = "budget";
+
+/// Calendar period of a budget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    /// Weeks starting on Monday.
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /// First day of the period which contains `d`.
+    pub fn start(&self, d: chrono::NaiveDate) -> chrono::NaiveDate {
+        match self {
+            Period::Week => d - chrono::Duration::days(
+                i64::from(d.weekday().num_days_from_monday())),
+            Period::Month => d.with_day(1).unwrap(),
+            Period::Year => d.with_ordinal(1).unwrap(),
+        }
+    }
+
+    /// First day of the period after the one which starts at `start`.
+    pub fn next(&self, start: chrono::NaiveDate) -> chrono::NaiveDate {
+        match self {
+            Period::Week => start + chrono::Duration::weeks(1),
+            Period::Month if start.month() == 12 =>
+                start.with_year(start.year() + 1).unwrap().with_month(1)
+                    .unwrap(),
+            Period::Month => start.with_month(start.month() + 1).unwrap(),
+            Period::Year => start.with_year(start.year() + 1).unwrap(),
+        }
+    }
+
+    /// Name of the period which starts at `start`, e.g. `2020-04`.
+    pub fn name(&self, start: chrono::NaiveDate) -> String {
+        match self {
+            Period::Week => start.format("%Y-%m-%d").to_string(),
+            Period::Month => start.format("%Y-%m").to_string(),
+            Period::Year => start.format("%Y").to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weekly" => Ok(Period::Week),
+            "monthly" => Ok(Period::Month),
+            "yearly" => Ok(Period::Year),
+            _ => Err(format!(r#"invalid period "{}""#, s)),
+        }
+    }
+}
+
+/// Spending limit for a tag and its descendants, written
+/// `<tag> <weekly|monthly|yearly> <amount><currency>`.
+#[derive(Debug, PartialEq)]
+pub struct Budget {
+    pub tag: Tag,
+    pub period: Period,
+    pub limit: Money,
+}
+
+impl Budget {
+    pub fn from_line(l: &str, opts: &db::Options) -> Result<Budget, String> {
+        let mut fields = l.split(' ');
+        let mut next = |name: &str| fields.next()
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| format!("missing {}", name));
+        let tag = next("tag")?.parse()?;
+        let period = next("period")?.parse()?;
+        let amount = next("amount")?;
+        let limit = db::Entry::parse_amount(amount, &opts.format)
+            .map_err(|e| e.to_string())?;
+        if limit.is_negative() {
+            return Err(format!(r#"invalid budget "{}""#, amount));
+        }
+        if let Some(x) = fields.next() {
+            return Err(format!(r#"unexpected field "{}""#, x));
+        }
+        Ok(Budget { tag, period, limit })
+    }
+}
+
+/// Reads the budgets in a file (see `db::read_config`).
+pub fn read(
+    path: &std::path::Path,
+    opts: &db::Options,
+) -> Result<Vec<Budget>, String> {
+    db::read_config(path, |s| parse(s, opts))
+}
+
+/// Parses budgets, one per line.
+fn parse(
+    s: &str,
+    opts: &db::Options,
+) -> Result<Vec<Budget>, (usize, db::EntryParseError)> {
+    let mut ret = Vec::new();
+    db::parse_config(s, |l| {
+        ret.push(Budget::from_line(l.trim_start(), opts)
+            .map_err(db::EntryParseError::new)?);
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+/// Budget and expenses of a tag in one period.
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub tag: Tag,
+    pub period: String,
+    pub budget: Money,
+    pub actual: Money,
+    pub remaining: Money,
+}
+
+impl Row {
+    pub fn exceeded(&self) -> bool {
+        self.remaining.is_negative()
+    }
+}
+
+/// Compares each budget with the net expenses of its tag in every period
+/// from the first entry of the tag up to the one which contains `today`.
+/// Values are converted to the currency of the budget using `to_eur`, the
+/// number of units of each currency per euro.
+pub fn report(
+    budgets: &[Budget],
+    v: &[db::Entry],
+    to_eur: &std::collections::HashMap<Commodity, dec::Decimal>,
+    today: chrono::NaiveDate,
+    rounding: dec::Rounding,
+) -> Result<Vec<Row>, money::Error> {
+    let mut ret = Vec::new();
+    for b in budgets {
+        let currency = b.limit.currency;
+        let conv = rates(to_eur, currency)?;
+        let entries: Vec<_> =
+            v.iter().filter(|x| x.tag.is_under(&b.tag)).collect();
+        let first = entries.iter().map(|x| x.date).min().unwrap_or(today);
+        let mut start = b.period.start(first.min(today));
+        while start <= today {
+            let end = b.period.next(start);
+            let it = entries.iter()
+                .copied()
+                .filter(|x| start <= x.date && x.date < end);
+            let (pos, neg) = db::Entry::total_with_conversion(
+                it, &conv, currency, rounding)?;
+            let actual = -pos.checked_add(neg)?;
+            ret.push(Row {
+                tag: b.tag.clone(),
+                period: b.period.name(start),
+                budget: b.limit,
+                actual,
+                remaining: b.limit.checked_sub(actual)?,
+            });
+            start = end;
+        }
+    }
+    Ok(ret)
+}
+
+/// Rates to convert each currency in `to_eur` to `to`.
+fn rates(
+    to_eur: &std::collections::HashMap<Commodity, dec::Decimal>,
+    to: Commodity,
+) -> Result<std::collections::HashMap<Commodity, dec::Decimal>, money::Error>
+{
+    let mut ret = std::collections::HashMap::new();
+    if let Some(r) = to_eur.get(&to) {
+        for (c, x) in to_eur {
+            ret.insert(*c, r.checked_div(*x)?);
+        }
+    }
+    ret.insert(to, dec::Decimal::from(1));
+    Ok(ret)
+}
+
+/// Writes rows as aligned columns: tag (displayed with `label`), period,
+/// budget, actual and remaining values.
+pub fn write(
+    out: &mut impl Write,
+    v: &[Row],
+    label: impl Fn(&Tag) -> String,
+) -> std::io::Result<()> {
+    let labels: Vec<_> = v.iter().map(|x| label(&x.tag)).collect();
+    let values: Vec<_> = v
+        .iter()
+        .map(|x| [
+            x.budget.to_string(),
+            x.actual.to_string(),
+            x.remaining.to_string(),
+        ])
+        .collect();
+    let tw = labels.iter().map(String::len).max().unwrap_or_default();
+    let pw = v.iter().map(|x| x.period.len()).max().unwrap_or_default();
+    let vw = values
+        .iter()
+        .flatten()
+        .map(String::len)
+        .max()
+        .unwrap_or_default();
+    let it = v.iter().zip(labels.iter()).zip(values.iter());
+    for ((x, l), [budget, actual, remaining]) in it {
+        writeln!(out, "{:tw$} {:pw$} {:>vw$} {:>vw$} {:>vw$}",
+            l, x.period, budget, actual, remaining,
+            tw = tw, pw = pw, vw = vw)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::dec;
+    use super::Budget;
+    use super::Commodity;
+    use super::Period;
+
+    fn date(s: &str) -> chrono::NaiveDate {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn period() {
+        let d = date("2020-12-17");
+        assert_eq!(Period::Week.start(d), date("2020-12-14"));
+        assert_eq!(Period::Month.start(d), date("2020-12-01"));
+        assert_eq!(Period::Year.start(d), date("2020-01-01"));
+        assert_eq!(Period::Week.next(date("2020-12-28")), date("2021-01-04"));
+        assert_eq!(Period::Month.next(date("2020-12-01")), date("2021-01-01"));
+        assert_eq!(Period::Month.next(date("2020-01-01")), date("2020-02-01"));
+        assert_eq!(Period::Year.next(date("2020-01-01")), date("2021-01-01"));
+        assert_eq!(Period::Month.name(date("2020-04-01")), "2020-04");
+        assert!("daily".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let v = super::parse("\
+# limits
+food monthly 400.00eur
+
+travel:flights yearly 1000.00eur ; per person
+", &db::Options::default()).unwrap();
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[0].tag.as_str(), "food");
+        assert_eq!(v[0].period, Period::Month);
+        assert_eq!(v[0].limit.to_string(), "400.00eur");
+        assert_eq!(v[1].period, Period::Year);
+        let opts = db::Options::default();
+        for x in &[
+            "food", "food monthly", "food daily 1.00eur",
+            "food monthly -1.00eur", "food monthly 1.00eur x",
+        ] {
+            assert!(Budget::from_line(x, &opts).is_err(), "{}", x);
+        }
+        assert_eq!(
+            super::parse("  \nfood monthly 1.00eur \n", &opts).unwrap().len(),
+            1);
+        let (n, e) = super::parse("\nfood monthly\n", &opts).unwrap_err();
+        assert_eq!((n, e.msg()), (2, "missing amount"));
+    }
+
+    #[test]
+    fn report() -> std::io::Result<()> {
+        let opts = db::Options::default();
+        let budgets = [
+            Budget::from_line("food monthly 90.00eur", &opts).unwrap(),
+            Budget::from_line("rent monthly 500.00eur", &opts).unwrap(),
+        ];
+        let entries: Vec<db::Entry> = [
+            "2020-03-20 -60.00eur food:groceries a",
+            "2020-04-02 -80.00eur food:restaurants b",
+            "2020-04-03 -3000jpy food:groceries c",
+            "2020-04-04 10.00eur food:groceries refund",
+            "2020-04-05 -500.00eur rent d",
+            "2020-04-05 -20.00eur transport e",
+        ].iter().map(|x| db::Entry::from_line(x).unwrap()).collect();
+        let to_eur = [
+            (Commodity::iso(b"eur"), dec::Decimal::from(1)),
+            (Commodity::iso(b"jpy"), dec::Decimal::from(120)),
+        ].iter().copied().collect();
+        let rows = super::report(
+            &budgets, &entries, &to_eur, date("2020-05-10"),
+            dec::Rounding::HalfEven).unwrap();
+        assert_eq!(
+            rows.iter().map(super::Row::exceeded).collect::<Vec<_>>(),
+            [false, true, false, false, false]);
+        let mut out = Vec::new();
+        super::write(&mut out, &rows, |t| t.to_string())?;
+        assert_eq!(std::str::from_utf8(&out).unwrap(), "\
+food 2020-03  90.00eur  60.00eur  30.00eur
+food 2020-04  90.00eur  95.00eur  -5.00eur
+food 2020-05  90.00eur   0.00eur  90.00eur
+rent 2020-04 500.00eur 500.00eur   0.00eur
+rent 2020-05 500.00eur   0.00eur 500.00eur
+");
+        let budgets = [Budget::from_line("food yearly 30000jpy", &opts)
+            .unwrap()];
+        let rows = super::report(
+            &budgets, &entries, &to_eur, date("2020-05-10"),
+            dec::Rounding::HalfEven).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].actual.to_string(), "18600jpy");
+        assert_eq!(rows[0].remaining.to_string(), "11400jpy");
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use std::convert::TryFrom;
 use std::io::prelude::*;
 
+use super::commodity::Commodity;
 use super::db;
 use super::dec;
 use super::PROG_NAME;
@@ -65,7 +65,7 @@ fn update_currencies(
         .create(true)
         .open(&path)?;
     v.iter()
-        .map(|x| write!(f, "{} {}\n", x.name_str(), x.to_eur.to_string()))
+        .map(|x| write!(f, "{} {}\n", x.name, x.to_eur.to_string()))
         .collect()
 }
 
@@ -76,21 +76,17 @@ fn read_currencies(
     for line in std::io::BufReader::new(r).lines() {
         let line = line?;
         let mut it = line.split(" ");
-        let mut name = it.next().unwrap_or_default().bytes();
+        let name = it.next().unwrap_or_default();
         let to_eur = it.next().unwrap_or_default();
         ret.push(db::Currency {
-            name: [
-                name.next().unwrap(),
-                name.next().unwrap(),
-                name.next().unwrap(),
-            ],
-            to_eur: dec::Decimal::try_from(to_eur)
-                .expect("invalid decimal in currency file"),
+            name: name.parse().map_err(|e| std::io::Error::new(
+                std::io::ErrorKind::InvalidData, e))?,
+            to_eur: db::Currency::parse_rate(to_eur)?,
         });
     }
     ret.push(db::Currency {
-        name: [b'e', b'u', b'r'],
-        to_eur: dec::Decimal::new(1.0),
+        name: Commodity::iso(b"eur"),
+        to_eur: dec::Decimal::from(1),
     });
     Ok(ret)
 }
@@ -106,6 +102,7 @@ fn create_dir(path: &std::path::Path) -> std::io::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use super::Commodity;
     use super::db;
     use super::dec;
 
@@ -120,39 +117,39 @@ ron 4.8373\nrub 83.2936\nsek 10.9543\nsgd 1.5510\nthb 35.269\ntry 7.5658
 usd 1.0837\nzar 20.5853\n" as &[u8])?;
         ret.sort_by(|l, r| l.partial_cmp(&r).unwrap());
         assert_eq!(ret, [
-            ([b'a', b'u', b'd'], dec::Decimal::new(1.7266)),
-            ([b'b', b'g', b'n'], dec::Decimal::new(1.9558)),
-            ([b'b', b'r', b'l'], dec::Decimal::new(5.7619)),
-            ([b'c', b'a', b'd'], dec::Decimal::new(1.5393)),
-            ([b'c', b'h', b'f'], dec::Decimal::new(1.0517)),
-            ([b'c', b'n', b'y'], dec::Decimal::new(7.6888)),
-            ([b'c', b'z', b'k'], dec::Decimal::new(27.447)),
-            ([b'd', b'k', b'k'], dec::Decimal::new(7.4582)),
-            ([b'e', b'u', b'r'], dec::Decimal::new(1.0)),
-            ([b'g', b'b', b'p'], dec::Decimal::new(0.88120)),
-            ([b'h', b'k', b'd'], dec::Decimal::new(8.3987)),
-            ([b'h', b'r', b'k'], dec::Decimal::new(7.5700)),
-            ([b'h', b'u', b'f'], dec::Decimal::new(355.02)),
-            ([b'i', b'd', b'r'], dec::Decimal::new(17001.63)),
-            ([b'i', b'l', b's'], dec::Decimal::new(3.8522)),
-            ([b'i', b'n', b'r'], dec::Decimal::new(83.3760)),
-            ([b'i', b's', b'k'], dec::Decimal::new(157.80)),
-            ([b'j', b'p', b'y'], dec::Decimal::new(116.39)),
-            ([b'k', b'r', b'w'], dec::Decimal::new(1335.34)),
-            ([b'm', b'x', b'n'], dec::Decimal::new(26.3957)),
-            ([b'm', b'y', b'r'], dec::Decimal::new(4.7634)),
-            ([b'n', b'o', b'k'], dec::Decimal::new(11.4843)),
-            ([b'n', b'z', b'd'], dec::Decimal::new(1.8181)),
-            ([b'p', b'h', b'p'], dec::Decimal::new(55.096)),
-            ([b'p', b'l', b'n'], dec::Decimal::new(4.5291)),
-            ([b'r', b'o', b'n'], dec::Decimal::new(4.8373)),
-            ([b'r', b'u', b'b'], dec::Decimal::new(83.2936)),
-            ([b's', b'e', b'k'], dec::Decimal::new(10.9543)),
-            ([b's', b'g', b'd'], dec::Decimal::new(1.5510)),
-            ([b't', b'h', b'b'], dec::Decimal::new(35.269)),
-            ([b't', b'r', b'y'], dec::Decimal::new(7.5658)),
-            ([b'u', b's', b'd'], dec::Decimal::new(1.0837)),
-            ([b'z', b'a', b'r'], dec::Decimal::new(20.5853)),
+            (Commodity::iso(b"aud"), dec::Decimal::new(17266, 4)),
+            (Commodity::iso(b"bgn"), dec::Decimal::new(19558, 4)),
+            (Commodity::iso(b"brl"), dec::Decimal::new(57619, 4)),
+            (Commodity::iso(b"cad"), dec::Decimal::new(15393, 4)),
+            (Commodity::iso(b"chf"), dec::Decimal::new(10517, 4)),
+            (Commodity::iso(b"cny"), dec::Decimal::new(76888, 4)),
+            (Commodity::iso(b"czk"), dec::Decimal::new(27447, 3)),
+            (Commodity::iso(b"dkk"), dec::Decimal::new(74582, 4)),
+            (Commodity::iso(b"eur"), dec::Decimal::from(1)),
+            (Commodity::iso(b"gbp"), dec::Decimal::new(88120, 5)),
+            (Commodity::iso(b"hkd"), dec::Decimal::new(83987, 4)),
+            (Commodity::iso(b"hrk"), dec::Decimal::new(75700, 4)),
+            (Commodity::iso(b"huf"), dec::Decimal::new(35502, 2)),
+            (Commodity::iso(b"idr"), dec::Decimal::new(1700163, 2)),
+            (Commodity::iso(b"ils"), dec::Decimal::new(38522, 4)),
+            (Commodity::iso(b"inr"), dec::Decimal::new(833760, 4)),
+            (Commodity::iso(b"isk"), dec::Decimal::new(15780, 2)),
+            (Commodity::iso(b"jpy"), dec::Decimal::new(11639, 2)),
+            (Commodity::iso(b"krw"), dec::Decimal::new(133534, 2)),
+            (Commodity::iso(b"mxn"), dec::Decimal::new(263957, 4)),
+            (Commodity::iso(b"myr"), dec::Decimal::new(47634, 4)),
+            (Commodity::iso(b"nok"), dec::Decimal::new(114843, 4)),
+            (Commodity::iso(b"nzd"), dec::Decimal::new(18181, 4)),
+            (Commodity::iso(b"php"), dec::Decimal::new(55096, 3)),
+            (Commodity::iso(b"pln"), dec::Decimal::new(45291, 4)),
+            (Commodity::iso(b"ron"), dec::Decimal::new(48373, 4)),
+            (Commodity::iso(b"rub"), dec::Decimal::new(832936, 4)),
+            (Commodity::iso(b"sek"), dec::Decimal::new(109543, 4)),
+            (Commodity::iso(b"sgd"), dec::Decimal::new(15510, 4)),
+            (Commodity::iso(b"thb"), dec::Decimal::new(35269, 3)),
+            (Commodity::iso(b"try"), dec::Decimal::new(75658, 4)),
+            (Commodity::iso(b"usd"), dec::Decimal::new(10837, 4)),
+            (Commodity::iso(b"zar"), dec::Decimal::new(205853, 4)),
         ]
             .iter()
             .copied()
@@ -160,4 +157,13 @@ usd 1.0837\nzar 20.5853\n" as &[u8])?;
             .collect::<Vec<_>>());
         Ok(())
     }
+
+    #[test]
+    fn read_currencies_invalid() {
+        for x in &["usd 0\n", "usd -1.5\n", "usd abc\n"] {
+            assert_eq!(
+                super::read_currencies(x.as_bytes()).unwrap_err().kind(),
+                std::io::ErrorKind::InvalidData);
+        }
+    }
 }
@@ -0,0 +1,272 @@
+use super::db;
+use super::iso4217;
+
+/// Maximum length of a commodity code.
+pub const MAX_LEN: usize = 15;
+
+/// Code of a currency (e.g. `eur`) or any other commodity (e.g. `btc`,
+/// `aapl`, `miles`): a sequence of one to `MAX_LEN` ASCII letters.
+///
+/// Codes are stored inline so that values remain `Copy`.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct Commodity {
+    len: u8,
+    b: [u8; MAX_LEN],
+}
+
+impl Commodity {
+    /// Three-letter code, e.g. `Commodity::iso(b"eur")`.
+    pub const fn iso(c: &[u8; 3]) -> Commodity {
+        let mut b = [0; MAX_LEN];
+        b[0] = c[0];
+        b[1] = c[1];
+        b[2] = c[2];
+        Commodity { len: 3, b }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.b[..self.len as usize]
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(self.as_bytes()).unwrap()
+    }
+
+    /// Number of decimal places used to parse and display values: the minor
+    /// units for ISO 4217 currencies, `None` (i.e. the values are kept as
+    /// written) for other commodities.
+    pub fn precision(&self) -> Option<u32> {
+        iso4217::minor_units(self.as_bytes())
+    }
+
+    /// The same code in lowercase letters.
+    pub fn to_lowercase(self) -> Commodity {
+        let mut ret = self;
+        ret.b.make_ascii_lowercase();
+        ret
+    }
+
+    /// Length of the code at the end of `s`, e.g. 3 for `10.50eur`.
+    pub fn suffix_len(s: &str) -> usize {
+        s.bytes().rev().take_while(u8::is_ascii_alphabetic).count()
+    }
+}
+
+/// Name of the file in the database directory which lists commodities other
+/// than ISO 4217 currencies.
+pub const FILE_NAME: &str = "commodities";
+
+/// Commodities accepted by `check`: ISO 4217 currencies and those listed in
+/// the commodities file.
+#[derive(Debug, Default)]
+pub struct Known {
+    custom: std::collections::BTreeSet<Commodity>,
+}
+
+impl Known {
+    /// Whether `c` is known, ignoring case.
+    pub fn contains(&self, c: &Commodity) -> bool {
+        c.precision().is_some() || self.custom.contains(&c.to_lowercase())
+    }
+
+    /// Known commodity closest to `c`, if any is similar enough to be a
+    /// likely typo.  Codes with the same letters (e.g. `eur` for `eru`) are
+    /// preferred, then custom commodities over currencies.
+    pub fn suggest(&self, c: &Commodity) -> Option<Commodity> {
+        let s = c.as_str().to_ascii_lowercase();
+        let max = (s.len() / 3).max(1);
+        let sorted = |x: &str| {
+            let mut v = x.as_bytes().to_vec();
+            v.sort_unstable();
+            v
+        };
+        let letters = sorted(&s);
+        let custom = self.custom.iter().map(|x| (*x, false));
+        let iso = iso4217::codes().filter_map(|x| x.parse().ok());
+        custom
+            .chain(iso.map(|x| (x, true)))
+            .map(|(x, iso): (Commodity, _)| {
+                let lower = x.as_str().to_ascii_lowercase();
+                let d = distance(&s, &lower);
+                ((d, sorted(&lower) != letters, iso), x)
+            })
+            .filter(|&((d, _, _), _)| d <= max)
+            .min_by_key(|x| x.0)
+            .map(|x| x.1)
+    }
+}
+
+/// Reads the list of custom commodities, one code per line (see
+/// `db::read_config`).
+pub fn read_known(path: &std::path::Path) -> Result<Known, String> {
+    db::read_config(path, parse_known)
+}
+
+/// Parses a list of commodities.
+fn parse_known(s: &str) -> Result<Known, (usize, db::EntryParseError)> {
+    let mut ret = Known::default();
+    db::parse_config(s, |l| {
+        let c: Commodity =
+            l.trim_start().parse().map_err(db::EntryParseError::new)?;
+        ret.custom.insert(c.to_lowercase());
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+/// Edit distance between two strings, where insertions, deletions,
+/// substitutions and transpositions of adjacent characters count as one
+/// edit.
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, x) in d.iter_mut().enumerate() {
+        x[0] = i;
+    }
+    for (j, x) in d[0].iter_mut().enumerate() {
+        *x = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+impl std::str::FromStr for Commodity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty()
+            || s.len() > MAX_LEN
+            || !s.bytes().all(|x| x.is_ascii_alphabetic())
+        {
+            return Err(format!(r#"invalid commodity "{}""#, s));
+        }
+        let mut b = [0; MAX_LEN];
+        b[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Commodity { len: s.len() as u8, b })
+    }
+}
+
+impl std::convert::TryFrom<String> for Commodity {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Commodity> for String {
+    fn from(c: Commodity) -> String {
+        String::from(c.as_str())
+    }
+}
+
+/// Codes are ordered alphabetically.
+impl PartialOrd for Commodity {
+    fn partial_cmp(&self, o: &Commodity) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(o))
+    }
+}
+
+impl Ord for Commodity {
+    fn cmp(&self, o: &Commodity) -> std::cmp::Ordering {
+        self.as_bytes().cmp(o.as_bytes())
+    }
+}
+
+impl std::fmt::Debug for Commodity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl std::fmt::Display for Commodity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Commodity;
+
+    #[test]
+    fn parse() {
+        let c: Commodity = "eur".parse().unwrap();
+        assert_eq!(c, Commodity::iso(b"eur"));
+        assert_eq!(c.as_str(), "eur");
+        assert_eq!("miles".parse::<Commodity>().unwrap().as_str(), "miles");
+        assert_ne!(
+            "eu".parse::<Commodity>().unwrap(),
+            "eur".parse::<Commodity>().unwrap());
+        for x in &["", "e1", "e r", "abcdefghijklmnop"] {
+            assert!(x.parse::<Commodity>().is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn order() {
+        let c = |s: &str| s.parse::<Commodity>().unwrap();
+        assert!(c("aapl") < c("usd"));
+        assert!(c("eu") < c("eur"));
+        assert!(c("eur") < c("usd"));
+    }
+
+    #[test]
+    fn precision() {
+        assert_eq!(Commodity::iso(b"eur").precision(), Some(2));
+        assert_eq!(Commodity::iso(b"jpy").precision(), Some(0));
+        assert_eq!(Commodity::iso(b"btc").precision(), None);
+        assert_eq!("aapl".parse::<Commodity>().unwrap().precision(), None);
+    }
+
+    #[test]
+    fn known() {
+        let c = |s: &str| s.parse::<Commodity>().unwrap();
+        let k = super::parse_known("\
+# crypto
+btc
+Miles ; airline
+").unwrap();
+        assert!(k.contains(&c("eur")));
+        assert!(k.contains(&c("EUR")));
+        assert!(k.contains(&c("btc")));
+        assert!(k.contains(&c("BTC")));
+        assert!(k.contains(&c("miles")));
+        assert!(!k.contains(&c("eru")));
+        assert_eq!(k.suggest(&c("eru")), Some(c("eur")));
+        assert_eq!(k.suggest(&c("usdd")), Some(c("usd")));
+        assert_eq!(k.suggest(&c("mils")), Some(c("miles")));
+        assert_eq!(k.suggest(&c("xxxxxx")), None);
+        assert_eq!(super::parse_known("btc\nb1\n").unwrap_err().0, 2);
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(super::distance("eur", "eur"), 0);
+        assert_eq!(super::distance("eru", "eur"), 1);
+        assert_eq!(super::distance("eu", "eur"), 1);
+        assert_eq!(super::distance("abc", "xyz"), 3);
+        assert_eq!(super::distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn suffix_len() {
+        assert_eq!(Commodity::suffix_len("10.50eur"), 3);
+        assert_eq!(Commodity::suffix_len("0.01btc"), 3);
+        assert_eq!(Commodity::suffix_len("1200miles"), 5);
+        assert_eq!(Commodity::suffix_len("12"), 0);
+    }
+}
@@ -1,90 +1,436 @@
 use std::convert::TryFrom;
-use std::io::prelude::*;
 
+use super::account::Account;
+use super::commodity::{self, Commodity};
 use super::dec;
+use super::duplicate;
+use super::expr;
+use super::money::{self, Money};
+use super::recurring;
+use super::tag::Tag;
 
 const DATE_FMT: &str = "%Y-%m-%d";
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, PartialOrd, serde::Serialize, serde::Deserialize)]
 pub struct Currency {
-    pub name: [u8; 3],
+    pub name: Commodity,
     pub to_eur: dec::Decimal,
 }
 
 impl Currency {
-    pub fn name_str(&self) -> &str {
-        std::str::from_utf8(&self.name).unwrap()
+    /// Parses an exchange rate, which has to be a positive decimal.
+    pub fn parse_rate(s: &str) -> std::io::Result<dec::Decimal> {
+        dec::Decimal::try_from(s)
+            .ok()
+            .filter(|x| !x.is_negative() && *x != dec::Decimal::default())
+            .ok_or_else(|| std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(r#"invalid exchange rate "{}""#, s)))
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Settings that affect how database files are parsed.  Files can change
+/// them for their remaining lines using directives.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub format: dec::Format,
+    pub blank_lines: BlankLines,
+}
+
+/// Treatment of blank lines in database files.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlankLines {
+    /// The rest of the file is ignored.
+    #[default]
+    Stop,
+    /// Blank lines are ignored and can be used as separators.
+    Skip,
+}
+
+impl Options {
+    /// Applies a directive line (e.g. `decimal-mark ,`) to the options.
+    pub fn apply_directive(
+        &mut self,
+        l: &str,
+    ) -> Result<(), EntryParseError> {
+        let mut fields = l.splitn(2, ' ');
+        let name = fields.next().unwrap_or_default();
+        let arg = fields.next().unwrap_or_default();
+        match name {
+            "decimal-mark" => {
+                self.format = arg.chars().next()
+                    .filter(|_| arg.chars().count() == 1)
+                    .and_then(dec::Format::with_decimal_mark)
+                    .ok_or_else(|| EntryParseError::new(
+                        format!(r#"invalid decimal mark "{}""#, arg)))?;
+                Ok(())
+            },
+            "blank-lines" => {
+                self.blank_lines = match arg {
+                    "stop" => BlankLines::Stop,
+                    "skip" => BlankLines::Skip,
+                    _ => return Err(EntryParseError::new(
+                        format!(r#"invalid blank-lines value "{}""#, arg))),
+                };
+                Ok(())
+            },
+            _ => Err(EntryParseError::new(
+                format!(r#"invalid directive "{}""#, name))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Entry {
     pub date: chrono::NaiveDate,
-    pub value: dec::Decimal,
-    pub currency: [u8; 3],
-    pub tag: u8,
+    pub value: Money,
+    /// Conversion to another currency, used instead of the exchange rates
+    /// from the cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<money::Price>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<Account>,
+    pub tag: Tag,
     pub text: String,
+    /// `#name` words in `text`, without the `#`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hashtags: Vec<String>,
+    /// `key=value` words in `text`, the first one is used if a key is
+    /// repeated.
+    #[serde(
+        default,
+        skip_serializing_if = "std::collections::BTreeMap::is_empty",
+    )]
+    pub meta: std::collections::BTreeMap<String, String>,
+    /// Identifier shared by the postings of a multi-line transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<usize>,
 }
 
 impl Entry {
+    #[cfg(test)]
     pub fn from_line(l: &str) -> Result<Entry, EntryParseError> {
-        let mut fields = l.split(' ');
+        Entry::from_line_with(l, &Options::default())
+    }
+
+    pub fn from_line_with(
+        l: &str,
+        opts: &Options,
+    ) -> Result<Entry, EntryParseError> {
+        Entry::parse_line(l, opts).map(|x| x.0)
+    }
+
+    /// Parses an entry and returns the column of its tag.
+    fn parse_line(
+        l: &str,
+        opts: &Options,
+    ) -> Result<(Entry, usize), EntryParseError> {
+        let mut fields = l.splitn(2, ' ');
         let date = match fields.next() {
             None | Some("") => return Err(
                 EntryParseError::new(String::from("missing date"))),
             Some(x) => x,
         };
-        let value = fields.next()
+        let rest = fields.next().unwrap_or_default();
+        Entry::parse_fields(Entry::parse_date(date)?, rest, opts)
+            .map(|(x, col)| (x, col + date.len() + 1))
+            .map_err(|e| e.offset(date.len() + 1))
+    }
+
+    /// Parses the fields of an entry after the date, which are also the
+    /// fields of each posting in a transaction.
+    pub fn parse_posting(
+        date: chrono::NaiveDate,
+        l: &str,
+        opts: &Options,
+    ) -> Result<Entry, EntryParseError> {
+        Entry::parse_fields(date, l, opts).map(|x| x.0)
+    }
+
+    /// Implementation of `parse_posting`, which also returns the column of
+    /// the tag.
+    fn parse_fields(
+        date: chrono::NaiveDate,
+        l: &str,
+        opts: &Options,
+    ) -> Result<(Entry, usize), EntryParseError> {
+        let mut fields = l.splitn(2, ' ');
+        let amount = fields.next()
+            .filter(|x| !x.is_empty())
             .ok_or_else(||
-                EntryParseError::new(String::from("missing amount")))
-            .and_then(|x|
-                if x.len() < 7 {
-                    Err(EntryParseError::new(
-                        format!(r#"invalid amount "{}""#, x)))
-                } else {
-                    Ok(x)
-                }
-            )?;
-        let mut currency = value[value.len() - 3..].bytes();
+                EntryParseError::new(String::from("missing amount")))?;
+        let (amount, price) = match amount.find('@') {
+            Some(i) => (&amount[..i], Some(&amount[i..])),
+            None => (amount, None),
+        };
+        let (value, currency) =
+            Entry::split_amount(amount).map_err(|e| e.field(0))?;
+        let value = Entry::parse_value(value, currency, &opts.format)
+            .map_err(|e| e.field(0))?;
+        let price = price
+            .map(|x| Entry::parse_price(x, &opts.format)
+                .map_err(|e| e.field(amount.len())))
+            .transpose()?;
+        let mut rest = fields.next().unwrap_or_default();
+        let account = if rest.starts_with('[') {
+            let col = l.len() - rest.len();
+            let (x, r) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+            rest = r.strip_prefix(' ').unwrap_or_default();
+            Some(Account::from_token(x)
+                .map_err(|e| EntryParseError::new(e).field(col))?)
+        } else {
+            None
+        };
+        let col = l.len() - rest.len();
+        let mut fields = rest.splitn(2, ' ');
         let tag = fields.next()
-            .ok_or_else(|| EntryParseError::new(String::from("missing tag")))?
-            .bytes().next().unwrap();
-        Ok(Entry {
-            date: Entry::parse_date(date)?,
-            value: dec::Decimal::try_from(&value[..value.len() - 3])
-                .map_err(|_|
-                    EntryParseError::new(
-                        String::from("invalid decimal in entry")))?,
-            currency: [
-                currency.next().unwrap(),
-                currency.next().unwrap(),
-                currency.next().unwrap(),
-            ],
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| EntryParseError::new(String::from("missing tag")))
+            .and_then(|x| x.parse().map_err(EntryParseError::new))
+            .map_err(|e| e.field(col))?;
+        let text = fields.next().unwrap_or_default();
+        let (hashtags, meta) = Entry::parse_text(text);
+        Ok((Entry {
+            date,
+            value: Money::new(value, currency),
+            price,
+            account,
             tag,
-            text: String::from(&l[date.len() + value.len() + 4..]),
-        })
+            text: String::from(text),
+            hashtags,
+            meta,
+            transaction: None,
+        }, col + 1))
     }
 
-    fn parse_date(s: &str) -> Result<chrono::NaiveDate, EntryParseError> {
+    /// Extracts hashtags and metadata from the description of an entry.
+    fn parse_text(
+        text: &str,
+    ) -> (Vec<String>, std::collections::BTreeMap<String, String>) {
+        let mut hashtags = Vec::new();
+        let mut meta = std::collections::BTreeMap::new();
+        for x in text.split_whitespace() {
+            if let Some(t) = x.strip_prefix('#') {
+                if !t.is_empty() {
+                    hashtags.push(String::from(t));
+                }
+            } else if let Some(i) = x.find('=').filter(|&i| i != 0) {
+                meta.entry(String::from(&x[..i]))
+                    .or_insert_with(|| String::from(&x[i + 1..]));
+            }
+        }
+        (hashtags, meta)
+    }
+
+
+    /// Splits an amount into the value and the commodity, e.g. `10.50eur`.
+    fn split_amount(s: &str) -> Result<(&str, Commodity), EntryParseError> {
+        let n = Commodity::suffix_len(s);
+        if n == 0 || n == s.len() {
+            return Err(EntryParseError::new(
+                format!(r#"invalid amount "{}""#, s)));
+        }
+        let (value, currency) = s.split_at(s.len() - n);
+        Ok((value, currency.parse().map_err(EntryParseError::new)?))
+    }
+
+    /// Parses an amount with its commodity, e.g. `10.50eur`.
+    pub fn parse_amount(
+        s: &str,
+        format: &dec::Format,
+    ) -> Result<Money, EntryParseError> {
+        let (value, currency) = Entry::split_amount(s)?;
+        Ok(Money::new(Entry::parse_value(value, currency, format)?, currency))
+    }
+
+    /// Parses a price annotation, `@<rate><currency>` or
+    /// `@@<total><currency>`.  Both must be positive.
+    fn parse_price(
+        s: &str,
+        format: &dec::Format,
+    ) -> Result<money::Price, EntryParseError> {
+        let err = || EntryParseError::new(
+            format!(r#"invalid price "{}""#, s));
+        let (total, amount) = match s.strip_prefix("@@") {
+            Some(x) => (true, x),
+            None => (false, &s[1..]),
+        };
+        let (value, currency) = Entry::split_amount(amount)?;
+        let ret = if total {
+            money::Price::Total(Entry::parse_amount(amount, format)?)
+        } else {
+            money::Price::Unit(Money::new(
+                format.parse(value).map_err(|_| err())?, currency))
+        };
+        match ret {
+            money::Price::Unit(x) | money::Price::Total(x)
+                if x.is_negative() || x.amount.is_zero() => Err(err()),
+            _ => Ok(ret),
+        }
+    }
+
+    pub fn parse_date(s: &str) -> Result<chrono::NaiveDate, EntryParseError> {
         chrono::NaiveDate::parse_from_str(s, DATE_FMT)
             .map_err(|x| EntryParseError::new(
                 format!(r#"invalid date "{}": {}"#, s, x.to_string())))
     }
 
+    /// Whether the value of an amount is an arithmetic expression rather
+    /// than a number.
+    pub fn is_expression(s: &str) -> bool {
+        s.contains(|c| "()*/".contains(c))
+            || s.get(1..).unwrap_or_default().contains(|c| "+-".contains(c))
+    }
+
+    /// Parses the amount of an entry, either a number or an arithmetic
+    /// expression (see `expr::eval`).
+    fn parse_value(
+        s: &str,
+        currency: Commodity,
+        format: &dec::Format,
+    ) -> Result<dec::Decimal, EntryParseError> {
+        let ret = if Entry::is_expression(s) {
+            expr::eval(s, format)
+                .map_err(|e| EntryParseError::with_col(
+                    format!(r#"invalid expression "{}": {}"#, s, e.msg),
+                    e.pos + 1))?
+                .normalize()
+        } else {
+            format.parse(s)
+                .map_err(|_|
+                    EntryParseError::new(
+                        String::from("invalid decimal in entry")))?
+        };
+        match currency.precision() {
+            Some(p) if ret.scale() > p => Err(EntryParseError::new(format!(
+                r#"too many decimal places in "{}" for {} (maximum: {})"#,
+                s, currency, p))),
+            _ => Ok(ret),
+        }
+    }
+
     pub fn to_line(&self) -> String {
-        format!(
-            "{} {:.2}{} {} {}",
+        let mut ret = format!(
+            "{} {}{}{} {}",
             self.date,
             self.value,
-            std::str::from_utf8(&self.currency).unwrap(),
-            self.tag as char,
-            self.text,
-        )
+            self.price.map_or_else(String::new, |x| x.to_string()),
+            self.account.as_ref()
+                .map_or_else(String::new, |x| format!(" [{}]", x)),
+            self.tag,
+        );
+        if !self.text.is_empty() {
+            ret.push(' ');
+            ret.push_str(&self.text);
+        }
+        ret
+    }
+
+    /// Changes to account balances caused by this entry: the value for a
+    /// single account, the negated value and the value for a transfer.  The
+    /// destination of a transfer receives the value in the currency of the
+    /// price, if there is one.
+    pub fn postings(&self) -> Result<Vec<(&str, Money)>, money::Error> {
+        Ok(match &self.account {
+            None => Vec::new(),
+            Some(Account::One(x)) => vec![(x, self.value)],
+            Some(Account::Transfer(from, to)) =>
+                vec![(from, -self.value), (to, self.priced_value()?)],
+        })
+    }
+
+    /// The value of the entry converted using its price, if it has one.
+    pub fn priced_value(&self) -> Result<Money, money::Error> {
+        match self.price {
+            Some(p) => p.apply(self.value),
+            None => Ok(self.value),
+        }
     }
 
-    pub fn unique_currencies(v: &[Entry]) -> Vec<[u8; 3]> {
+    /// Verifies that all entries have an account and that the values of
+    /// each transaction (the postings of a multi-line transaction or entries
+    /// with the same date and description) add up to zero in each currency.
+    /// Transfers are always balanced, values with a price are counted in the
+    /// currency of the price, rounded with `rounding`.  Returns all errors,
+    /// unbalanced transactions are located at their first entry.
+    pub fn check_balanced(
+        v: &[(Entry, Location)],
+        rounding: dec::Rounding,
+    ) -> Result<(), Vec<DBError>> {
+        // Errors and the index of the entry they are located at.
+        let mut errors = Vec::new();
+        let mut txns = std::collections::BTreeMap::<_, Vec<usize>>::new();
+        for (i, (x, _)) in v.iter().enumerate() {
+            if x.account.is_none() {
+                errors.push((i, format!("missing account: {}", x.to_line())));
+                continue;
+            }
+            let key = match x.transaction {
+                Some(t) => (x.date, Some(t), ""),
+                None => (x.date, None, x.text.as_str()),
+            };
+            txns.entry(key).or_default().push(i);
+        }
+        let sum = |txn: &[usize]| {
+            let mut sums = std::collections::BTreeMap::new();
+            for x in txn.iter().map(|&i| &v[i].0) {
+                if let Some(Account::Transfer(..)) = x.account {
+                    continue;
+                }
+                let m = x.priced_value()?.round(rounding);
+                let sum = sums
+                    .entry(m.currency)
+                    .or_insert_with(|| Money::zero(m.currency));
+                *sum = sum.checked_add(m)?;
+            }
+            Ok::<_, money::Error>(sums)
+        };
+        for txn in txns.values() {
+            let (i, first) = (txn[0], &v[txn[0]].0);
+            match sum(txn) {
+                Err(e) => errors.push((i, e.to_string())),
+                Ok(sums) => if let Some(m) =
+                    sums.values().find(|x| !x.amount.is_zero())
+                {
+                    errors.push((i, format!(
+                        r#"unbalanced transaction {} "{}": {}"#,
+                        first.date, first.text, m)));
+                },
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        // Transactions are grouped by date, errors are kept in file order.
+        errors.sort_by_key(|x| x.0);
+        Err(errors.into_iter()
+            .map(|(i, msg)| DBError::new(ErrorKind::Balance(msg))
+                .at(&v[i].1.path, v[i].1.line))
+            .collect())
+    }
+
+    /// Splits the entry into several entries with values proportional to
+    /// the ratios in `parts`, each optionally with a different tag.  See
+    /// `Money::allocate`.
+    pub fn split(
+        &self,
+        parts: &[(dec::Decimal, Option<Tag>)],
+    ) -> Option<Vec<Entry>> {
+        let ratios: Vec<_> = parts.iter().map(|x| x.0).collect();
+        Some(self.value.allocate(&ratios)?
+            .into_iter()
+            .zip(parts)
+            .map(|(value, (_, tag))| Entry {
+                value,
+                tag: tag.clone().unwrap_or_else(|| self.tag.clone()),
+                ..self.clone()
+            })
+            .collect())
+    }
+
+    pub fn unique_currencies(v: &[Entry]) -> Vec<Commodity> {
         v.iter()
-            .map(|x| x.currency)
+            .map(|x| x.value.currency)
             .collect::<std::collections::HashSet<_>>()
             .iter()
             .copied()
@@ -93,72 +439,425 @@ impl Entry {
 
     pub fn total<'a>(
         it: impl Iterator<Item = &'a Entry>,
-    ) -> Vec<([u8; 3], dec::Decimal, dec::Decimal)> {
+    ) -> Result<Vec<(Money, Money)>, money::Error> {
         let mut ret = std::collections::HashMap::new();
         for x in it {
+            let v = x.value;
             let (pos, neg) = ret
-                .entry(x.currency)
-                .or_insert((dec::Decimal::new(0.0), dec::Decimal::new(0.0)));
-            if x.value < dec::Decimal::new(0.0) {
-                *neg += x.value
+                .entry(v.currency)
+                .or_insert((Money::zero(v.currency), Money::zero(v.currency)));
+            if v.is_negative() {
+                *neg = neg.checked_add(v)?
             } else {
-                *pos += x.value
+                *pos = pos.checked_add(v)?
             }
         }
-        ret.iter().map(|(k, v)| (*k, v.0, v.1)).collect()
+        Ok(ret.values().copied().collect())
     }
 
+    /// Converts the totals of `it` to currency `to` using the rates in
+    /// `conv`.  Entries with a price are converted individually using the
+    /// price, and then `conv` if its currency is not `to`.
     pub fn total_with_conversion<'a>(
         it: impl Iterator<Item = &'a Entry>,
-        conv: &std::collections::HashMap<[u8; 3], dec::Decimal>,
-    ) -> (dec::Decimal, dec::Decimal) {
-        Entry::total(it).iter().fold(
-            (dec::Decimal::new(0.0), dec::Decimal::new(0.0)),
-            |(pos, neg), (cur, p, n)| {
-                let c = conv[cur];
-                (pos + *p * c, neg + *n * c)
-            },
-        )
+        conv: &std::collections::HashMap<Commodity, dec::Decimal>,
+        to: Commodity,
+        rounding: dec::Rounding,
+    ) -> Result<(Money, Money), money::Error> {
+        let convert = |m: Money| {
+            let c = *conv.get(&m.currency)
+                .ok_or(money::Error::MissingRate(m.currency))?;
+            Ok::<_, money::Error>(m.convert(c, to)?.round(rounding))
+        };
+        let (priced, plain): (Vec<_>, Vec<_>) =
+            it.partition(|x| x.price.is_some());
+        let init = Entry::total(plain.into_iter())?.iter().try_fold(
+            (Money::zero(to), Money::zero(to)),
+            |(pos, neg), (p, n)| Ok::<_, money::Error>((
+                pos.checked_add(convert(*p)?)?,
+                neg.checked_add(convert(*n)?)?,
+            )),
+        )?;
+        priced.iter().try_fold(init, |(pos, neg), x| {
+            let m = x.price.unwrap().apply(x.value)?;
+            let m = if m.currency == to {
+                m.round(rounding)
+            } else {
+                convert(m)?
+            };
+            Ok(if m.is_negative() {
+                (pos, neg.checked_add(m)?)
+            } else {
+                (pos.checked_add(m)?, neg)
+            })
+        })
     }
 
-    pub fn check_db(path: &std::path::Path) -> Result<(), DBError> {
-        match DBIterator::new(path)?.find(Result::is_err) {
-            Some(e) => Err(e.unwrap_err()),
-            None => Ok(()),
+    /// Verifies all entries and assertions in the database and returns all
+    /// errors found.  Assertions are verified with all the entries up to
+    /// their date, wherever they are.  All commodities must be in `known`.
+    pub fn check_db(
+        path: &std::path::Path,
+        opts: &Options,
+        known: &commodity::Known,
+    ) -> Result<(), Vec<DBError>> {
+        let check = |c: Commodity, loc: &Location| {
+            if known.contains(&c) {
+                return Ok(());
+            }
+            let hint = known.suggest(&c)
+                .map(|x| format!(r#" (did you mean "{}"?)"#, x))
+                .unwrap_or_default();
+            Err(DBError::new(ErrorKind::Commodity(
+                format!(r#"unknown commodity "{}"{}"#, c, hint)))
+                .at(&loc.path, loc.line))
+        };
+        let mut it = DBIterator::new(path, opts).map_err(|e| vec![e.into()])?;
+        let mut entries = Vec::new();
+        let mut assertions = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(x) = it.next_item() {
+            let ret = match x {
+                Err(e) => Err(e),
+                Ok(Item::Entry(x, loc)) => {
+                    let price = match x.price {
+                        Some(money::Price::Unit(p))
+                            | Some(money::Price::Total(p)) => Some(p),
+                        None => None,
+                    };
+                    entries.push(*x);
+                    let x = entries.last().unwrap();
+                    check(x.value.currency, &loc)
+                        .and(price.map_or(Ok(()), |p| check(p.currency, &loc)))
+                },
+                Ok(Item::Assertion(a, loc)) => {
+                    let ret = check(a.value.currency, &loc);
+                    if ret.is_ok() {
+                        assertions.push((errors.len(), a, loc));
+                    }
+                    ret
+                },
+                Ok(Item::Include(_)) => unreachable!(),
+            };
+            if let Err(e) = ret {
+                errors.push(e);
+            }
+        }
+        // Assertions are verified with all entries up to their date, even
+        // those read after them.  Failures are kept in file order.
+        for (i, a, loc) in assertions.into_iter().rev() {
+            if let Err(e) = a.check(&entries) {
+                errors.insert(i, DBError::new(ErrorKind::Assertion(e))
+                    .at(&loc.path, loc.line));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reports duplicated entries, see `duplicate::find`.  Each error is
+    /// located at the second entry and mentions the location of the first.
+    pub fn check_duplicates(
+        path: &std::path::Path,
+        opts: &Options,
+        days: i64,
+    ) -> Result<(), Vec<DBError>> {
+        let (entries, locs): (Vec<_>, Vec<_>) =
+            Entry::read_db_located(path, opts)
+                .map_err(|e| vec![e])?
+                .into_iter()
+                .unzip();
+        let errors: Vec<_> = duplicate::find(&entries, days)
+            .into_iter()
+            .map(|x| {
+                let (first, second) = (&locs[x.first], &locs[x.second]);
+                let kind =
+                    if x.exact { "duplicate" } else { "possible duplicate" };
+                DBError::new(ErrorKind::Duplicate(format!(
+                    "{} of {}:{}: {}", kind, first.path.display(), first.line,
+                    entries[x.first].to_line())))
+                    .at(&second.path, second.line)
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn read_db(
+        path: &std::path::Path,
+        opts: &Options,
+    ) -> Result<Vec<Entry>, DBError> {
+        DBIterator::new(path, opts)?.collect()
+    }
+
+    /// Reads the database, keeping the location of each entry.
+    pub fn read_db_located(
+        path: &std::path::Path,
+        opts: &Options,
+    ) -> Result<Vec<(Entry, Location)>, DBError> {
+        let mut it = DBIterator::new(path, opts)?;
+        let mut ret = Vec::new();
+        while let Some(x) = it.next_item() {
+            if let Item::Entry(x, loc) = x? {
+                ret.push((*x, loc));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Canonical paths of all the files of the database, including those
+    /// included by others, in the order they are read.
+    pub fn read_db_files(
+        path: &std::path::Path,
+        opts: &Options,
+    ) -> Result<Vec<std::path::PathBuf>, DBError> {
+        let mut it = DBIterator::new(path, opts)?;
+        while let Some(x) = it.next_item() {
+            x?;
         }
+        Ok(it.read)
     }
 
-    pub fn read_db(path: &std::path::Path) -> Result<Vec<Entry>, DBError> {
-        DBIterator::new(path)?.collect()
+    /// Reads the database and adds the entries generated by its recurring
+    /// rules up to `until` which are not already in it.
+    pub fn read_db_until(
+        path: &std::path::Path,
+        opts: &Options,
+        until: chrono::NaiveDate,
+    ) -> Result<Vec<Entry>, DBError> {
+        let mut ret = Entry::read_db(path, opts)?;
+        let rules = recurring::read(&path.join(recurring::FILE_NAME), opts)
+            .map_err(|e| DBError::new(ErrorKind::ParseError(e)))?;
+        let v = recurring::expand(&rules, &ret, until);
+        ret.extend(v);
+        ret.sort_by_key(|x| x.date);
+        Ok(ret)
+    }
+}
+
+/// Where the balance of an assertion is calculated.
+#[derive(Debug, PartialEq)]
+pub enum AssertionTarget {
+    /// Postings of an account.
+    Account(String),
+    /// Values of the entries with a tag or one of its descendants.
+    Tag(Tag),
+}
+
+/// Expected balance at the end of a date, written
+/// `<date> = <amount><currency> <[account]|tag>` in database files.
+#[derive(Debug, PartialEq)]
+pub struct Assertion {
+    pub date: chrono::NaiveDate,
+    pub value: Money,
+    pub target: AssertionTarget,
+}
+
+impl Assertion {
+    pub fn from_line(
+        l: &str,
+        opts: &Options,
+    ) -> Result<Assertion, EntryParseError> {
+        let mut fields = l.splitn(4, ' ');
+        let date = Entry::parse_date(fields.next().unwrap_or_default())?;
+        match fields.next() {
+            Some("=") => {},
+            x => return Err(EntryParseError::new(format!(
+                r#"expected "=", found "{}""#, x.unwrap_or_default()))),
+        }
+        let amount = fields.next()
+            .filter(|x| !x.is_empty())
+            .ok_or_else(||
+                EntryParseError::new(String::from("missing amount")))?;
+        let value = Entry::parse_amount(amount, &opts.format)?;
+        let target = match fields.next().filter(|x| !x.is_empty()) {
+            None => return Err(EntryParseError::new(
+                String::from("missing account or tag"))),
+            Some(x) if x.starts_with('[') =>
+                match Account::from_token(x).map_err(EntryParseError::new)? {
+                    Account::One(x) => AssertionTarget::Account(x),
+                    Account::Transfer(..) => return Err(EntryParseError::new(
+                        format!(r#"invalid account "{}""#, x))),
+                },
+            Some(x) => AssertionTarget::Tag(
+                x.parse().map_err(EntryParseError::new)?),
+        };
+        Ok(Assertion { date, value, target })
+    }
+
+    /// Balance of the target in the currency of the assertion, calculated
+    /// from the entries up to its date.
+    pub fn balance(&self, v: &[Entry]) -> Result<Money, money::Error> {
+        let currency = self.value.currency;
+        let mut ret = Money::zero(currency);
+        for x in v.iter().filter(|x| x.date <= self.date) {
+            match &self.target {
+                AssertionTarget::Account(a) =>
+                    for (b, m) in x.postings()? {
+                        if b == a && m.currency == currency {
+                            ret = ret.checked_add(m)?;
+                        }
+                    },
+                AssertionTarget::Tag(t) =>
+                    if x.tag.is_under(t) && x.value.currency == currency {
+                        ret = ret.checked_add(x.value)?;
+                    },
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Verifies that the balance calculated from `v` is the expected one.
+    pub fn check(&self, v: &[Entry]) -> Result<(), String> {
+        let b = self.balance(v).map_err(|e| e.to_string())?;
+        if b == self.value {
+            return Ok(());
+        }
+        let target = match &self.target {
+            AssertionTarget::Account(x) => format!("[{}]", x),
+            AssertionTarget::Tag(x) => x.to_string(),
+        };
+        Err(format!(
+            "balance assertion failed for {} on {}: expected {}, found {}",
+            target, self.date, self.value, b))
     }
 }
 
 #[derive(Debug)]
 pub struct EntryParseError {
     msg: String,
+    col: Option<usize>,
 }
 
 impl EntryParseError {
-    fn new(msg: String) -> EntryParseError {
-        EntryParseError { msg }
+    pub fn new(msg: String) -> EntryParseError {
+        EntryParseError { msg, col: None }
+    }
+
+    fn with_col(msg: String, col: usize) -> EntryParseError {
+        EntryParseError { msg, col: Some(col) }
+    }
+
+    /// Adjusts the column for a field which starts at byte `n` of the line.
+    pub fn offset(self, n: usize) -> EntryParseError {
+        EntryParseError { col: self.col.map(|x| x + n), ..self }
+    }
+
+    /// Sets the column for an error in a field which starts at byte `n`,
+    /// pointing to the start of the field if the error has no column.
+    pub fn field(self, n: usize) -> EntryParseError {
+        EntryParseError { col: Some(self.col.unwrap_or(1) + n), ..self }
+    }
+
+    pub fn col(&self) -> Option<usize> {
+        self.col
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl std::fmt::Display for EntryParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.col {
+            Some(col) => write!(f, "column {}: {}", col, self.msg),
+            None => write!(f, "{}", self.msg),
+        }
     }
 }
 
 #[derive(Debug)]
-pub enum DBError {
-    ParseError(EntryParseError),
+pub enum ErrorKind {
+    ParseError(String),
     IOError(std::io::Error),
+    Assertion(String),
+    Commodity(String),
+    Duplicate(String),
+    Tag(String),
+    Balance(String),
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IOError(e) => write!(f, "{}", e),
+            Self::ParseError(e)
+                | Self::Assertion(e)
+                | Self::Commodity(e)
+                | Self::Duplicate(e)
+                | Self::Tag(e)
+                | Self::Balance(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Error in a database, with its location when it is related to a line.
+/// Lines and columns start at 1.
+#[derive(Debug)]
+pub struct DBError {
+    pub path: Option<std::path::PathBuf>,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+    pub kind: ErrorKind,
+}
+
+impl DBError {
+    fn new(kind: ErrorKind) -> DBError {
+        DBError { path: None, line: None, col: None, kind }
+    }
+
+    /// Sets the location of the error, unless it already has one.
+    fn at(self, path: &std::path::Path, line: usize) -> DBError {
+        if self.path.is_some() {
+            return self;
+        }
+        DBError {
+            path: Some(std::path::PathBuf::from(path)),
+            line: Some(line),
+            ..self
+        }
+    }
+}
+
+/// Formats errors as `<file>:<line>:<col>: <message>` when the location is
+/// known, the column defaults to the start of the line.
+impl std::fmt::Display for DBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.path, self.line, self.col) {
+            (Some(p), Some(l), c) => write!(
+                f, "{}:{}:{}: {}", p.display(), l, c.unwrap_or(1), self.kind),
+            (Some(p), None, _) => write!(f, "{}: {}", p.display(), self.kind),
+            (_, _, Some(c)) => write!(f, "column {}: {}", c, self.kind),
+            _ => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for DBError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<EntryParseError> for DBError {
     fn from(e: EntryParseError) -> DBError {
-        Self::ParseError(e)
+        DBError { col: e.col, ..DBError::new(ErrorKind::ParseError(e.msg)) }
     }
 }
 
 impl From<std::io::Error> for DBError {
     fn from(e: std::io::Error) -> DBError {
-        Self::IOError(e)
+        DBError::new(ErrorKind::IOError(e))
     }
 }
 
@@ -199,11 +898,21 @@ impl Iterator for Find {
 #[derive(Debug)]
 struct DBIterator {
     files: Vec<std::path::PathBuf>,
-    file_it: Option<FileIterator>,
+    opts: Options,
+    /// Files being read, the last one is the innermost included file.
+    stack: Vec<FileIterator<std::io::BufReader<std::fs::File>>>,
+    /// Canonical paths of all files read so far.
+    visited: std::collections::HashSet<std::path::PathBuf>,
+    /// Canonical paths of all files read so far, in order.
+    read: Vec<std::path::PathBuf>,
+    next_txn: usize,
 }
 
 impl DBIterator {
-    fn new(path: &std::path::Path) -> std::io::Result<DBIterator> {
+    fn new(
+        path: &std::path::Path,
+        opts: &Options,
+    ) -> std::io::Result<DBIterator> {
         let mut files = Find::new(path)
             .collect::<std::io::Result<Vec<std::path::PathBuf>>>()?;
         files.retain(|x| x.extension().unwrap_or_default() == "txt");
@@ -211,9 +920,86 @@ impl DBIterator {
         files.reverse();
         Ok(DBIterator {
             files,
-            file_it: None,
+            opts: opts.clone(),
+            stack: Vec::new(),
+            visited: std::collections::HashSet::new(),
+            read: Vec::new(),
+            next_txn: 0,
         })
     }
+
+    /// Starts reading a file, unless it has already been read.  It is an
+    /// error to include a file which is currently being read.
+    fn open(&mut self, path: &std::path::Path) -> Result<(), DBError> {
+        let path = path.canonicalize()?;
+        if self.stack.iter().any(|x| x.path == path) {
+            let mut v: Vec<_> = self.stack.iter()
+                .map(|x| x.path.display().to_string())
+                .collect();
+            v.push(path.display().to_string());
+            return Err(EntryParseError::new(
+                format!("include cycle: {}", v.join(" -> "))).into());
+        }
+        if !self.visited.insert(path.clone()) {
+            return Ok(());
+        }
+        self.read.push(path.clone());
+        let mut it = FileIterator::new(&path, &self.opts)?;
+        it.next_txn = match self.stack.last() {
+            Some(x) => x.next_txn,
+            None => self.next_txn,
+        };
+        self.stack.push(it);
+        Ok(())
+    }
+
+    /// Handles an `include` directive in the innermost file.  `path` is
+    /// relative to the directory of that file.
+    fn include(&mut self, path: &str) -> Result<(), DBError> {
+        let it = self.stack.last().unwrap();
+        let loc = it.location();
+        let full = it.path.parent().unwrap().join(path);
+        self.open(&full)
+            .map_err(|e| match e.kind {
+                ErrorKind::IOError(e) => std::io::Error::new(
+                    e.kind(), format!(r#"include "{}": {}"#, path, e)).into(),
+                _ => e,
+            })
+            .map_err(|e| e.at(&loc.path, loc.line))
+    }
+}
+
+impl DBIterator {
+    /// Reads the next entry or assertion, `include` directives are handled
+    /// internally.
+    fn next_item(&mut self) -> Option<Result<Item, DBError>> {
+        loop {
+            let it = match self.stack.last_mut() {
+                Some(x) => x,
+                None => {
+                    let path = self.files.pop()?;
+                    if let Err(e) = self.open(&path) {
+                        return Some(Err(DBError { path: Some(path), ..e }));
+                    }
+                    continue;
+                },
+            };
+            match it.next_item() {
+                Some(Ok(Item::Include(x))) =>
+                    if let Err(e) = self.include(&x) {
+                        return Some(Err(e));
+                    },
+                Some(x) => return Some(x),
+                None => {
+                    let txn = self.stack.pop().unwrap().next_txn;
+                    match self.stack.last_mut() {
+                        Some(x) => x.next_txn = txn,
+                        None => self.next_txn = txn,
+                    }
+                },
+            }
+        }
+    }
 }
 
 impl Iterator for DBIterator {
@@ -221,72 +1007,448 @@ impl Iterator for DBIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if self.file_it.is_none() {
-                match self.files.pop() {
-                    None => return None,
-                    Some(x) => match FileIterator::new(&x) {
-                        Ok(x) => self.file_it = Some(x),
-                        Err(e) => return Some(Err(e.into())),
-                    },
-                }
-            }
-            if let Some(x) = self.file_it.as_mut().unwrap().next() {
-                return Some(x);
+            match self.next_item()? {
+                Ok(Item::Entry(x, _)) => return Some(Ok(*x)),
+                Ok(_) => {},
+                Err(e) => return Some(Err(e)),
             }
-            self.file_it = None;
         }
     }
 }
 
+/// Line of a database file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub path: std::path::PathBuf,
+    pub line: usize,
+    /// Column of the tag of an entry, 1 for other elements.
+    pub col: usize,
+}
+
+/// Element of a database file.
+#[derive(Debug)]
+enum Item {
+    /// Entry and its location.
+    Entry(Box<Entry>, Location),
+    /// Balance assertion and its location.
+    Assertion(Box<Assertion>, Location),
+    /// `include <path>` directive.
+    Include(String),
+}
+
 #[derive(Debug)]
-struct FileIterator {
-    lines: std::io::Lines<std::io::BufReader<std::fs::File>>,
+struct FileIterator<R: std::io::BufRead> {
+    lines: std::iter::Peekable<std::io::Lines<R>>,
+    /// Path of the file, empty when reading from other sources.
+    path: std::path::PathBuf,
+    /// Number of the last line read.
+    line: usize,
+    opts: Options,
+    /// Postings of the current transaction not yet returned and their
+    /// locations.
+    pending: std::collections::VecDeque<(Entry, Location)>,
+    /// Set after an error reading the file, which stops further reads.
+    failed: bool,
+    /// Identifier of the next multi-line transaction.
+    next_txn: usize,
 }
 
-impl FileIterator {
-    fn new(path: &std::path::Path) -> std::io::Result<FileIterator> {
+impl FileIterator<std::io::BufReader<std::fs::File>> {
+    fn new(
+        path: &std::path::Path,
+        opts: &Options,
+    ) -> std::io::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        if f.metadata()?.is_dir() {
+            return Err(std::io::Error::other("is a directory"));
+        }
         Ok(FileIterator {
-            lines: std::io::BufReader::new(std::fs::File::open(path)?).lines(),
+            path: std::path::PathBuf::from(path),
+            ..FileIterator::from_reader(std::io::BufReader::new(f), opts)
         })
     }
 }
 
-impl Iterator for FileIterator {
+impl<R: std::io::BufRead> FileIterator<R> {
+    fn from_reader(r: R, opts: &Options) -> Self {
+        FileIterator {
+            lines: r.lines().peekable(),
+            path: std::path::PathBuf::new(),
+            line: 0,
+            opts: opts.clone(),
+            pending: std::collections::VecDeque::new(),
+            failed: false,
+            next_txn: 0,
+        }
+    }
+
+    /// Location of the last line read.
+    fn location(&self) -> Location {
+        Location { path: self.path.clone(), line: self.line, col: 1 }
+    }
+
+    /// Adds the location of the last line read to an error, if the path of
+    /// the file is known.
+    fn error(&self, e: impl Into<DBError>) -> DBError {
+        self.error_at(e, self.line)
+    }
+
+    /// Adds the location of a line to an error, if the path of the file is
+    /// known.
+    fn error_at(&self, e: impl Into<DBError>, line: usize) -> DBError {
+        if self.path.as_os_str().is_empty() {
+            e.into()
+        } else {
+            e.into().at(&self.path, line)
+        }
+    }
+
+    /// Reads the next line, the file is not read further after an error.
+    fn next_line(&mut self) -> Option<Result<String, DBError>> {
+        if self.failed {
+            return None;
+        }
+        let ret = self.lines.next()?;
+        self.line += 1;
+        if ret.is_err() {
+            self.failed = true;
+        }
+        Some(ret.map_err(|e| self.error(e)))
+    }
+
+    /// Whether the next line which is not blank is an indented posting.
+    /// Lines with only whitespace or a comment are skipped, empty lines are
+    /// not as they separate entries.
+    fn next_is_posting(&mut self) -> bool {
+        while let Some(Ok(l)) = self.lines.peek() {
+            if l.is_empty() || !strip_comment(l).trim().is_empty() {
+                return l.starts_with(char::is_whitespace);
+            }
+            self.lines.next();
+            self.line += 1;
+        }
+        false
+    }
+
+    /// Skips the indented postings which follow the last line read.
+    fn skip_postings(&mut self) {
+        while self.next_is_posting() {
+            self.lines.next();
+            self.line += 1;
+        }
+    }
+
+    /// Reads the indented postings which follow a transaction header
+    /// (`<date> <description>`).  The description of the header is prepended
+    /// to that of each posting.  If a posting is invalid, the entire
+    /// transaction is skipped.
+    fn read_transaction(
+        &mut self,
+        header: &str,
+        line: usize,
+    ) -> Result<(), DBError> {
+        let mut fields = header.splitn(2, ' ');
+        let date = Entry::parse_date(fields.next().unwrap_or_default())
+            .map_err(|e| self.error_at(e, line))?;
+        let text = fields.next().unwrap_or_default();
+        let first = text.split(&[' ', '@'][..]).next().unwrap_or_default();
+        if Entry::parse_amount(first, &self.opts.format).is_ok() {
+            self.skip_postings();
+            return Err(self.error_at(EntryParseError::new(
+                String::from("posting after a complete entry")), line));
+        }
+        let id = self.next_txn;
+        self.next_txn += 1;
+        while self.next_is_posting() {
+            let l = self.next_line().unwrap()?;
+            let l = strip_comment(&l);
+            let p = l.trim_start();
+            if p.is_empty() {
+                continue;
+            }
+            let indent = l.len() - p.len();
+            let (mut e, col) = match Entry::parse_fields(date, p, &self.opts) {
+                Ok(x) => x,
+                Err(e) => {
+                    let e = self.error(e.offset(indent));
+                    self.pending.clear();
+                    self.skip_postings();
+                    return Err(e);
+                },
+            };
+            e.text = match (text, e.text.as_str()) {
+                (t, "") | ("", t) => String::from(t),
+                (t, x) => format!("{} {}", t, x),
+            };
+            let (hashtags, meta) = Entry::parse_text(&e.text);
+            e.hashtags = hashtags;
+            e.meta = meta;
+            e.transaction = Some(id);
+            let loc = Location { col: col + indent, ..self.location() };
+            self.pending.push_back((e, loc));
+        }
+        Ok(())
+    }
+}
+
+impl<R: std::io::BufRead> FileIterator<R> {
+    fn next_item(&mut self) -> Option<Result<Item, DBError>> {
+        if let Some((x, loc)) = self.pending.pop_front() {
+            return Some(Ok(Item::Entry(Box::new(x), loc)));
+        }
+        while let Some(x) = self.next_line() {
+            let x = match x {
+                Err(e) => return Some(Err(e)),
+                Ok(x) => x,
+            };
+            if x.is_empty() {
+                match self.opts.blank_lines {
+                    BlankLines::Stop => return None,
+                    BlankLines::Skip => continue,
+                }
+            }
+            let x = strip_comment(&x);
+            if x.trim().is_empty() {
+                continue;
+            }
+            if x.starts_with(char::is_whitespace) {
+                return Some(Err(self.error(EntryParseError::new(
+                    String::from("posting without a transaction header")))));
+            }
+            if x.split(' ').nth(1) == Some("=") {
+                return Some(Assertion::from_line(x, &self.opts)
+                    .map(|a| Item::Assertion(Box::new(a), self.location()))
+                    .map_err(|e| self.error(e)));
+            }
+            if !x.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                // Blank lines skipped by `next_is_posting` move `self.line`.
+                let loc = self.location();
+                if !self.next_is_posting() {
+                    return Some(Entry::parse_line(x, &self.opts)
+                        .map_err(|e| self.error_at(e, loc.line))
+                        .map(|(x, col)| {
+                            let loc = Location { col, ..loc };
+                            Item::Entry(Box::new(x), loc)
+                        }));
+                }
+                if let Err(e) = self.read_transaction(x, loc.line) {
+                    return Some(Err(e));
+                }
+                if let Some((x, loc)) = self.pending.pop_front() {
+                    return Some(Ok(Item::Entry(Box::new(x), loc)));
+                }
+                continue;
+            }
+            if let Some(path) = x.strip_prefix("include ") {
+                return Some(match path {
+                    "" => Err(self.error(EntryParseError::new(
+                        String::from("missing include path")))),
+                    _ => Ok(Item::Include(String::from(path))),
+                });
+            }
+            if let Err(e) = self.opts.apply_directive(x) {
+                return Some(Err(self.error(e)));
+            }
+        }
+        None
+    }
+}
+
+/// Reads entries from any source, `include` directives are not supported and
+/// assertions are ignored.
+impl<R: std::io::BufRead> Iterator for FileIterator<R> {
     type Item = Result<Entry, DBError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.lines.next() {
-            None => None,
-            Some(x) => match x {
-                Err(e) => Some(Err(e.into())),
-                Ok(x) if x == "" => None,
-                Ok(x) => Some(Entry::from_line(&x).map_err(Into::into)),
-            }
+        loop {
+            return Some(match self.next_item()? {
+                Ok(Item::Entry(x, _)) => Ok(*x),
+                Ok(Item::Assertion(..)) => continue,
+                Ok(Item::Include(_)) => Err(EntryParseError::new(
+                    String::from(
+                        "include is only supported in database files"))
+                    .into()),
+                Err(e) => Err(e),
+            });
         }
     }
 }
 
+/// Removes comments from a line: the entire line if it starts with `#` or `;`,
+/// otherwise everything after a `;` preceded by whitespace.  Inline comments
+/// cannot start with `#`, which is used for hashtags in descriptions.
+pub fn strip_comment(l: &str) -> &str {
+    if l.trim_start().starts_with(['#', ';']) {
+        return "";
+    }
+    let mut prev = ' ';
+    for (i, c) in l.char_indices() {
+        if c == ';' && prev.is_whitespace() {
+            return l[..i].trim_end();
+        }
+        prev = c;
+    }
+    l
+}
+
+/// Reads a file of the database directory other than entries (e.g. the
+/// budgets) with `parse`.  A file which does not exist is read as an empty
+/// file.  Errors are formatted as `<path>:<line>:<col>: <message>`.
+pub fn read_config<T>(
+    path: &std::path::Path,
+    parse: impl FnOnce(&str) -> Result<T, (usize, EntryParseError)>,
+) -> Result<T, String> {
+    let s = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    parse(&s).map_err(|(n, e)| format!(
+        "{}:{}:{}: {}", path.display(), n, e.col().unwrap_or(1), e.msg()))
+}
+
+/// Calls `f` with each line of `s` which is not blank, without comments and
+/// trailing whitespace.  Errors contain the line number.
+pub fn parse_config(
+    s: &str,
+    mut f: impl FnMut(&str) -> Result<(), EntryParseError>,
+) -> Result<(), (usize, EntryParseError)> {
+    for (i, l) in s.lines().enumerate() {
+        let l = strip_comment(l).trim_end();
+        if !l.trim_start().is_empty() {
+            f(l).map_err(|e| (i + 1, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Temporary directory for tests, removed with its contents when dropped.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> std::io::Result<TempDir> {
+        let dir = std::env::temp_dir()
+            .join(format!("nummi-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(TempDir(dir.canonicalize()?))
+    }
+
+    /// Writes the file `p`, creating its parent directories.
+    pub fn write(&self, p: &str, s: &str) -> std::io::Result<()> {
+        let p = self.join(p);
+        std::fs::create_dir_all(p.parent().unwrap())?;
+        std::fs::write(p, s)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::Account;
+    use super::Assertion;
+    use super::AssertionTarget;
+    use super::Commodity;
+    use super::commodity;
     use super::DATE_FMT;
+    use super::DBError;
     use super::Entry;
+    use super::ErrorKind;
+    use super::FileIterator;
+    use super::Item;
+    use super::Location;
+    use super::Money;
+    use super::Options;
+    use super::TempDir;
     use super::dec;
+    use super::money;
+
+    fn tag(s: &str) -> super::Tag {
+        s.parse().unwrap()
+    }
 
-    const EUR: [u8; 3] = [b'e', b'u', b'r'];
-    const USD: [u8; 3] = [b'u', b's', b'd'];
-    const GBP: [u8; 3] = [b'g', b'b', b'p'];
+    /// Entries parsed from `v`, located at consecutive lines of `a.txt`.
+    fn located(v: &[&str]) -> Vec<(Entry, Location)> {
+        v.iter()
+            .enumerate()
+            .map(|(i, x)| (
+                Entry::from_line(x).unwrap(),
+                Location { path: "a.txt".into(), line: i + 1, col: 1 }))
+            .collect()
+    }
+
+    const EUR: Commodity = Commodity::iso(b"eur");
+    const USD: Commodity = Commodity::iso(b"usd");
+    const GBP: Commodity = Commodity::iso(b"gbp");
+
+    #[test]
+    fn parse_rate() {
+        assert_eq!(
+            super::Currency::parse_rate("1.0837").unwrap(),
+            dec::Decimal::new(10837, 4));
+        for x in &["", "0", "0.000", "-1.5", "nan", "inf"] {
+            assert_eq!(
+                super::Currency::parse_rate(x).unwrap_err().kind(),
+                std::io::ErrorKind::InvalidData);
+        }
+    }
 
     #[test]
     fn from_line() {
         let e = Entry::from_line("2020-04-20 -100.00eur t description")
             .unwrap();
         assert_eq!(e.date.format(DATE_FMT).to_string(), "2020-04-20");
-        assert_eq!(e.value, super::dec::Decimal::new(-100.0));
-        assert_eq!(e.currency, EUR);
-        assert_eq!(e.tag, b't');
+        assert_eq!(e.value, Money::new(dec::Decimal::from(-100), EUR));
+        assert_eq!(e.tag, tag("t"));
         assert_eq!(e.text, "description");
     }
 
+    #[test]
+    fn hierarchical_tag() {
+        let e = Entry::from_line("2020-04-20 -10.00eur food:groceries apples")
+            .unwrap();
+        assert_eq!(e.tag, tag("food:groceries"));
+        assert_eq!(e.text, "apples");
+        assert_eq!(e.to_line(), "2020-04-20 -10.00eur food:groceries apples");
+        let e = Entry::from_line("2020-04-20 -10.00eur t").unwrap();
+        assert_eq!(e.text, "");
+        assert_eq!(
+            &Entry::from_line("2020-04-20 -10.00eur food: x").unwrap_err().msg,
+            r#"invalid tag "food:""#);
+    }
+
+    #[test]
+    fn hashtags_and_meta() {
+        let e = Entry::from_line(
+            "2020-04-20 -10.00eur t dinner #trip-lisbon receipt=2020/123.pdf \
+             #food =x # a=b=c k=")
+            .unwrap();
+        assert_eq!(e.hashtags, ["trip-lisbon", "food"]);
+        assert_eq!(
+            e.meta.iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect::<Vec<_>>(),
+            [("a", "b=c"), ("k", ""), ("receipt", "2020/123.pdf")]);
+        assert_eq!(e.to_line(), "2020-04-20 -10.00eur t dinner #trip-lisbon \
+            receipt=2020/123.pdf #food =x # a=b=c k=");
+    }
+
     #[test]
     fn parse_error() {
         assert_eq!(&Entry::from_line("").unwrap_err().msg, "missing date");
@@ -301,18 +1463,621 @@ mod tests {
             "missing tag");
     }
 
+    #[test]
+    fn minor_units() {
+        let e = Entry::from_line("2020-04-20 -1500jpy t description")
+            .unwrap();
+        assert_eq!(e.value.amount, dec::Decimal::from(-1500));
+        assert_eq!(e.to_line(), "2020-04-20 -1500jpy t description");
+        let e = Entry::from_line("2020-04-20 1.5bhd t description")
+            .unwrap();
+        assert_eq!(e.to_line(), "2020-04-20 1.500bhd t description");
+        assert_eq!(
+            &Entry::from_line("2020-04-20 1.5jpy t description")
+                .unwrap_err().msg,
+            r#"too many decimal places in "1.5" for jpy (maximum: 0)"#);
+        assert_eq!(
+            &Entry::from_line("2020-04-20 1.001eur t description")
+                .unwrap_err().msg,
+            r#"too many decimal places in "1.001" for eur (maximum: 2)"#);
+    }
+
+    #[test]
+    fn commodities() {
+        for x in &["0.01btc", "0.00012btc", "5aapl", "1200miles"] {
+            let l = format!("2020-04-20 {} t description", x);
+            assert_eq!(Entry::from_line(&l).unwrap().to_line(), l);
+        }
+        let e = Entry::from_line("2020-04-20 5aapl t x").unwrap();
+        assert_eq!(e.value.currency.as_str(), "aapl");
+        for x in &["12", "eur", "1abcdefghijklmnop"] {
+            let l = format!("2020-04-20 {} t x", x);
+            assert!(Entry::from_line(&l).is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn account() {
+        let e = Entry::from_line("2020-04-20 -10.00eur [checking] t x")
+            .unwrap();
+        assert_eq!(
+            e.account, Some(Account::One(String::from("checking"))));
+        assert_eq!(e.tag, tag("t"));
+        assert_eq!(e.text, "x");
+        assert_eq!(e.to_line(), "2020-04-20 -10.00eur [checking] t x");
+        let e = Entry::from_line("2020-04-20 10.00eur [checking>savings] t")
+            .unwrap();
+        assert_eq!(e.postings().unwrap(), [
+            ("checking", Money::new(dec::Decimal::new(-1000, 2), EUR)),
+            ("savings", Money::new(dec::Decimal::new(1000, 2), EUR)),
+        ]);
+        assert_eq!(
+            &Entry::from_line("2020-04-20 10.00eur [checking]")
+                .unwrap_err().msg,
+            "missing tag");
+        assert_eq!(
+            &Entry::from_line("2020-04-20 10.00eur [a b] t x")
+                .unwrap_err().msg,
+            r#"invalid account "[a""#);
+    }
+
+    #[test]
+    fn price() {
+        let czk = "czk".parse().unwrap();
+        let e = Entry::from_line(
+            "2020-04-20 1000.00eur@@25500czk [checking>czk] t transfer")
+            .unwrap();
+        assert_eq!(e.postings().unwrap(), [
+            ("checking", Money::new(dec::Decimal::from(-1000), EUR)),
+            ("czk", Money::new(dec::Decimal::from(25500), czk)),
+        ]);
+        assert!(Entry::check_balanced(&located(&[
+            "2020-04-20 -1000.00eur@@1100usd [checking] t exchange",
+            "2020-04-20 1100.00usd [usd] t exchange",
+        ]), dec::Rounding::HalfEven).is_ok());
+        let e = Entry::from_line(
+            "2020-04-20 -1000.00eur@@25500czk [checking] t transfer")
+            .unwrap();
+        assert_eq!(
+            e.price,
+            Some(money::Price::Total(
+                Money::new(dec::Decimal::from(25500), czk))));
+        assert_eq!(
+            e.to_line(),
+            "2020-04-20 -1000.00eur@@25500.00czk [checking] t transfer");
+        let e = Entry::from_line("2020-04-20 25500czk@0.03921eur t x")
+            .unwrap();
+        assert_eq!(
+            e.price,
+            Some(money::Price::Unit(
+                Money::new(dec::Decimal::new(3921, 5), EUR))));
+        assert_eq!(e.to_line(), "2020-04-20 25500.00czk@0.03921eur t x");
+        for x in &["@", "@@", "@1", "@eur", "@-1eur", "@0eur", "@@1.001eur"] {
+            let l = format!("2020-04-20 10.00usd{} t x", x);
+            assert!(Entry::from_line(&l).is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn check_balanced() {
+        let check = |v: &[(Entry, Location)]| {
+            Entry::check_balanced(v, dec::Rounding::HalfEven)
+                .map_err(|v| v.iter()
+                    .map(DBError::to_string)
+                    .collect::<Vec<_>>())
+        };
+        assert_eq!(check(&located(&[
+            "2020-04-20 -20.00eur [checking] food groceries",
+            "2020-04-20 20.00eur [expenses] food groceries",
+            "2020-04-20 500.00eur [checking>savings] transfer",
+            "2020-04-21 -1000jpy [cash] food lunch",
+            "2020-04-21 1000jpy [expenses] food lunch",
+        ])), Ok(()));
+        assert_eq!(check(&located(&[
+            "2020-04-22 -10.00eur [checking] food lunch",
+            "2020-04-22 3.33usd@3.003eur [expenses] food lunch",
+        ])), Ok(()));
+        assert_eq!(
+            check(&located(&[
+                "2020-04-21 -2.00eur [cash] food coffee",
+                "2020-04-20 -20.00eur [checking] food groceries",
+                "2020-04-20 15.00eur [expenses] food groceries",
+                "2020-04-20 1.00eur t x",
+            ])),
+            Err(vec![
+                String::from(concat!(
+                    r#"a.txt:1:1: unbalanced transaction 2020-04-21 "#,
+                    r#""coffee": -2.00eur"#)),
+                String::from(concat!(
+                    r#"a.txt:2:1: unbalanced transaction 2020-04-20 "#,
+                    r#""groceries": -5.00eur"#)),
+                String::from(
+                    "a.txt:4:1: missing account: 2020-04-20 1.00eur t x"),
+            ]));
+        let mut it = FileIterator::from_reader(b"\
+2020-04-20 supermarket
+  -30.00eur [checking] food
+  30.00eur [expenses] food
+2020-04-20 supermarket
+  -10.00eur [checking] food
+  15.00eur [expenses] food
+" as &[u8], &Options::default());
+        let mut v = Vec::new();
+        while let Some(Ok(Item::Entry(x, loc))) = it.next_item() {
+            v.push((*x, Location { path: "a.txt".into(), ..loc }));
+        }
+        assert_eq!(check(&v[..2]), Ok(()));
+        assert_eq!(
+            check(&v),
+            Err(vec![String::from(concat!(
+                r#"a.txt:5:1: unbalanced transaction 2020-04-20 "#,
+                r#""supermarket": 5.00eur"#))]));
+    }
+
+    #[test]
+    fn decimal_mark() {
+        let opts = Options {
+            format: dec::Format::with_decimal_mark(',').unwrap(),
+            ..Options::default()
+        };
+        let e = Entry::from_line_with("2020-04-20 -1.234,56eur t x", &opts)
+            .unwrap();
+        assert_eq!(e.value, Money::new(dec::Decimal::new(-123456, 2), EUR));
+        assert_eq!(e.to_line(), "2020-04-20 -1234.56eur t x");
+        assert!(Entry::from_line("2020-04-20 -1.234,56eur t x").is_err());
+    }
+
+    #[test]
+    fn directives() {
+        let v = FileIterator::from_reader(b"\
+2020-04-20 1234.56usd t a
+decimal-mark .
+2020-04-20 1,234.56usd t b
+decimal-mark ,
+2020-04-20 1.234,56usd t c
+" as &[u8], &Options::default())
+            .map(|x| x.unwrap().to_line())
+            .collect::<Vec<_>>();
+        assert_eq!(v, [
+            "2020-04-20 1234.56usd t a",
+            "2020-04-20 1234.56usd t b",
+            "2020-04-20 1234.56usd t c",
+        ]);
+        let mut it = FileIterator::from_reader(
+            b"decimal-mark x\nunknown\n" as &[u8], &Options::default());
+        let msgs = [
+            r#"invalid decimal mark "x""#,
+            r#"invalid directive "unknown""#,
+        ];
+        for msg in &msgs {
+            match it.next() {
+                Some(Err(DBError { kind: ErrorKind::ParseError(e), .. })) =>
+                    assert_eq!(&e, msg),
+                x => panic!("unexpected result: {:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let read = |s: &'static str| FileIterator::from_reader(
+            s.as_bytes(), &Options::default())
+            .map(|x| x.unwrap().to_line())
+            .collect::<Vec<_>>();
+        assert_eq!(read("\
+# comment
+; comment
+2020-04-20 1.00eur t a ; comment
+2020-04-20 2.00eur t b #trip;x
+
+2020-04-20 3.00eur t c
+"), [
+            "2020-04-20 1.00eur t a",
+            "2020-04-20 2.00eur t b #trip;x",
+        ]);
+        assert_eq!(read("\
+blank-lines skip ; separate entries with blank lines
+2020-04-20 1.00eur t a
+
+2020-04-20 2.00eur t b
+blank-lines stop
+
+2020-04-20 3.00eur t c
+"), [
+            "2020-04-20 1.00eur t a",
+            "2020-04-20 2.00eur t b",
+        ]);
+        let mut it = FileIterator::from_reader(
+            b"blank-lines x\n" as &[u8], &Options::default());
+        match it.next() {
+            Some(Err(DBError { kind: ErrorKind::ParseError(e), .. })) =>
+                assert_eq!(e, r#"invalid blank-lines value "x""#),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn transaction() {
+        let v = FileIterator::from_reader(b"\
+2020-04-20 -1.00eur t a
+2020-04-21 supermarket #weekly
+  -30.00eur food:groceries
+  ; comment
+  -10.00eur household soap
+    -5.00eur [cash] gifts ; comment
+2020-04-22 -2.00eur t b
+2020-04-23
+  1.00eur t c
+" as &[u8], &Options::default())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            v.iter().map(Entry::to_line).collect::<Vec<_>>(), [
+                "2020-04-20 -1.00eur t a",
+                "2020-04-21 -30.00eur food:groceries supermarket #weekly",
+                "2020-04-21 -10.00eur household supermarket #weekly soap",
+                "2020-04-21 -5.00eur [cash] gifts supermarket #weekly",
+                "2020-04-22 -2.00eur t b",
+                "2020-04-23 1.00eur t c",
+            ]);
+        assert_eq!(
+            v.iter().map(|x| x.transaction).collect::<Vec<_>>(),
+            [None, Some(0), Some(0), Some(0), None, Some(1)]);
+        assert_eq!(v[2].hashtags, ["weekly"]);
+        let mut it = FileIterator::from_reader(
+            b"2020-04-20 -1.00eur [cash] t a\n2020-04-21 x\n  -2.00eur t b\n"
+                as &[u8],
+            &Options::default());
+        let mut loc = || match it.next_item() {
+            Some(Ok(Item::Entry(_, loc))) => (loc.line, loc.col),
+            x => panic!("unexpected result: {:?}", x),
+        };
+        assert_eq!(loc(), (1, 28));
+        assert_eq!(loc(), (3, 12));
+        let v = FileIterator::from_reader(
+            b"2020-04-20 -1.00eur t a\n   \n2020-04-21 -2.00eur t b\n\t\n"
+                as &[u8],
+            &Options::default())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(v.iter().map(Entry::to_line).collect::<Vec<_>>(), [
+            "2020-04-20 -1.00eur t a",
+            "2020-04-21 -2.00eur t b",
+        ]);
+        assert_eq!(v[0].transaction, None);
+        let mut it = FileIterator::from_reader(
+            b"2020-04-20 shop\n  \n2020-04-21 -2.00eur t b\n" as &[u8],
+            &Options::default());
+        assert_eq!(
+            it.next().unwrap().unwrap_err().to_string(),
+            r#"column 12: invalid amount "shop""#);
+        assert_eq!(it.next().unwrap().unwrap().to_line(),
+            "2020-04-21 -2.00eur t b");
+        let mut it = FileIterator::from_reader(b"\
+2020-04-20 -10.00eur t lunch
+  -5.00eur t tip
+2020-04-21 -2.00eur t b
+" as &[u8], &Options::default());
+        assert_eq!(
+            it.next().unwrap().unwrap_err().to_string(),
+            "posting after a complete entry");
+        assert_eq!(it.next().unwrap().unwrap().to_line(),
+            "2020-04-21 -2.00eur t b");
+        assert!(it.next().is_none());
+        let mut it = FileIterator::from_reader(b"  1.00eur t a\n\
+2020-04-20 x
+  1.00eur t a
+  1.00 t b
+  1.00eur t c
+2020-04-20 1.00eur t d
+" as &[u8], &Options::default());
+        let mut next = || it.next().map(|x| x.map(|x| x.to_line()));
+        match next() {
+            Some(Err(DBError { kind: ErrorKind::ParseError(e), .. })) =>
+                assert_eq!(e, "posting without a transaction header"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match next() {
+            Some(Err(DBError { kind: ErrorKind::ParseError(e), .. })) => {
+                assert_eq!(e, r#"invalid amount "1.00""#);
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert_eq!(
+            next().unwrap().unwrap(), "2020-04-20 1.00eur t d");
+        assert!(next().is_none());
+    }
+
+    #[test]
+    fn include() -> std::io::Result<()> {
+        let dir = TempDir::new("include")?;
+        dir.write("shared.txt", "2020-04-20 1.00eur t shared\n")?;
+        dir.write("db/a.txt", "\
+2020-04-19 2.00eur t a
+include ../shared.txt
+include sub/b.txt
+2020-04-21 3.00eur t a
+")?;
+        dir.write("db/sub/b.txt", "2020-04-22 4.00eur t b\n")?;
+        dir.write("cycle/c.txt", "include d.txt\n")?;
+        dir.write("cycle/d.txt", "include c.txt\n")?;
+        dir.write("missing/e.txt", "2020-04-19 2.00eur t a\ninclude x.txt\n")?;
+        dir.write("dir/f.txt", "include sub\n")?;
+        dir.write("dir/sub/g.dat", "")?;
+        let ret = Entry::read_db(&dir.join("db"), &Options::default())
+            .map(|v| v.iter().map(Entry::to_line).collect::<Vec<_>>());
+        let files = Entry::read_db_files(&dir.join("db"), &Options::default());
+        let known = commodity::Known::default();
+        let cycle =
+            Entry::check_db(&dir.join("cycle"), &Options::default(), &known);
+        let missing =
+            Entry::check_db(&dir.join("missing"), &Options::default(), &known);
+        let is_dir =
+            Entry::check_db(&dir.join("dir"), &Options::default(), &known);
+        assert_eq!(ret.unwrap(), [
+            "2020-04-19 2.00eur t a",
+            "2020-04-20 1.00eur t shared",
+            "2020-04-22 4.00eur t b",
+            "2020-04-21 3.00eur t a",
+        ]);
+        assert_eq!(files.unwrap(), [
+            dir.join("db/a.txt"), dir.join("shared.txt"),
+            dir.join("db/sub/b.txt"),
+        ]);
+        let c = dir.join("cycle/c.txt").display().to_string();
+        let d = dir.join("cycle/d.txt").display().to_string();
+        assert_eq!(
+            cycle.unwrap_err()[0].to_string(),
+            format!("{}:1:1: include cycle: {} -> {} -> {}", d, c, d, c));
+        match missing.as_ref().map_err(Vec::as_slice) {
+            Err([e @ DBError { kind: ErrorKind::IOError(io), .. }]) => {
+                assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+                let prefix = format!(
+                    r#"{}:2:1: include "x.txt": "#,
+                    dir.join("missing/e.txt").display());
+                assert!(e.to_string().starts_with(&prefix), "{}", e);
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert_eq!(
+            is_dir.unwrap_err()[0].to_string(),
+            format!(
+                r#"{}:1:1: include "sub": is a directory"#,
+                dir.join("dir/f.txt").display()));
+        Ok(())
+    }
+
+    #[test]
+    fn assertion() {
+        let v: Vec<_> = [
+            "2020-04-20 1000.00eur [checking] salary",
+            "2020-04-21 -20.00eur [checking] food:groceries",
+            "2020-04-21 -1000jpy [checking] food:restaurants",
+            "2020-04-22 100.00eur [checking>savings] transfer",
+            "2020-05-02 -30.00eur [checking] food:groceries",
+        ].iter().map(|x| Entry::from_line(x).unwrap()).collect();
+        let a = |l| Assertion::from_line(l, &Options::default()).unwrap();
+        let x = a("2020-05-01 = 880.00eur [checking]");
+        assert_eq!(
+            x.target, AssertionTarget::Account(String::from("checking")));
+        assert_eq!(x.check(&v), Ok(()));
+        assert_eq!(a("2020-05-01 = -1000jpy [checking]").check(&v), Ok(()));
+        assert_eq!(a("2020-05-01 = 100eur [savings]").check(&v), Ok(()));
+        assert_eq!(a("2020-05-31 = -50eur food").check(&v), Ok(()));
+        assert_eq!(a("2020-05-31 = 850.00eur [checking]").check(&v), Ok(()));
+        assert_eq!(
+            a("2020-04-21 = -30.00eur food:groceries").check(&v),
+            Err(String::from(concat!(
+                "balance assertion failed for food:groceries on ",
+                "2020-04-21: expected -30.00eur, found -20.00eur"))));
+        for x in &[
+            "2020-05-01 =", "2020-05-01 = 1eur", "2020-05-01 = 1 t",
+            "2020-05-01 = 1eur [a>b]", "x = 1eur t", "2020-05-01 == 1eur t",
+            "2020-05-01 1eur t",
+        ] {
+            assert!(Assertion::from_line(x, &Options::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn check_db_assertion() -> std::io::Result<()> {
+        let dir = TempDir::new("assertion")?;
+        dir.write("a.txt", "\
+2020-04-20 1000.00eur [checking] salary
+2020-04-21 = 980.00eur [checking]
+2020-04-21 -20.00eur [checking] groceries
+2020-04-30 = 990.00eur [checking]
+2020-05-01 = 1000.00eur [checking]
+")?;
+        dir.write("b.txt", "2020-04-25 10.00eur [checking] refund\n")?;
+        let ret = Entry::check_db(
+            &dir, &Options::default(), &commodity::Known::default());
+        match ret.as_ref().map_err(Vec::as_slice) {
+            Err([e @ DBError { kind: ErrorKind::Assertion(_), .. }]) =>
+                assert_eq!(e.to_string(), format!(
+                    "{}:5:1: balance assertion failed for [checking] on {}",
+                    dir.join("a.txt").display(),
+                    "2020-05-01: expected 1000.00eur, found 990.00eur")),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_db_commodity() -> std::io::Result<()> {
+        let dir = TempDir::new("commodity")?;
+        dir.write("a.txt", "\
+2020-04-19 -20.00eur t groceries
+2020-04-20 0.5btc@8000.00usd t coins
+2020-04-21 shop
+  -10.00eur t a
+  -20.00eru t b
+")?;
+        let check = |custom| {
+            let known = commodity::read_known(&dir.join(custom)).unwrap();
+            Entry::check_db(&dir, &Options::default(), &known)
+        };
+        dir.write("commodities", "btc\n")?;
+        let ret = check("commodities");
+        let ret_btc = check("missing");
+        let path = dir.join("a.txt");
+        let eru = format!(
+            r#"{}:5:1: unknown commodity "eru" (did you mean "eur"?)"#,
+            path.display());
+        let btc = format!(
+            r#"{}:2:1: unknown commodity "btc" (did you mean "btn"?)"#,
+            path.display());
+        let msgs = |ret: Result<(), Vec<DBError>>| ret.unwrap_err()
+            .iter()
+            .map(|e| match e.kind {
+                ErrorKind::Commodity(_) => e.to_string(),
+                _ => panic!("unexpected error: {:?}", e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(msgs(ret), std::slice::from_ref(&eru));
+        assert_eq!(msgs(ret_btc), [btc, eru]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_db_errors() -> std::io::Result<()> {
+        let dir = TempDir::new("errors")?;
+        dir.write("a.txt", "\
+2020-04-19 -20.00eur t groceries
+2020-04-19 -20.00 t groceries
+2020-04-20 -(1+)eur t x
+2020-13-01 1.00eur t x
+2020-04-21 shop
+  1.00eur t a
+  1.00 t b
+2020-04-21 = 1.00eur
+unknown
+2020-04-22 1.00eur t ok
+2020-04-23 -10.00eur t lunch
+  -5.00eur t tip
+")?;
+        let ret = Entry::check_db(
+            &dir, &Options::default(), &commodity::Known::default());
+        let path = dir.join("a.txt");
+        let msgs: Vec<_> = ret.unwrap_err().iter()
+            .map(|e| e.to_string()
+                .strip_prefix(&path.display().to_string())
+                .map(String::from)
+                .unwrap())
+            .collect();
+        assert_eq!(msgs, [
+            r#":2:12: invalid amount "-20.00""#,
+            r#":3:16: invalid expression "-(1+)": expected number, found ")""#,
+            r#":4:1: invalid date "2020-13-01": input is out of range"#,
+            r#":7:3: invalid amount "1.00""#,
+            ":8:1: missing account or tag",
+            r#":9:1: invalid directive "unknown""#,
+            ":11:1: posting after a complete entry",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_duplicates() -> std::io::Result<()> {
+        let dir = TempDir::new("duplicates")?;
+        dir.write("a.txt", "\
+2020-04-19 -20.00eur t groceries
+2020-04-19 -2.00eur t coffee
+2020-04-19 -2.00eur t coffee duplicate=ok
+")?;
+        dir.write("b.txt", "\
+2020-04-19 -20.00eur t groceries
+2020-04-20 -20.00eur food groceries
+")?;
+        let ret = Entry::check_duplicates(&dir, &Options::default(), 3);
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        let msgs: Vec<_> =
+            ret.unwrap_err().iter().map(DBError::to_string).collect();
+        assert_eq!(msgs, [
+            format!(
+                "{}:1:1: duplicate of {}:1: {}", b.display(), a.display(),
+                "2020-04-19 -20.00eur t groceries"),
+            format!(
+                "{}:2:1: possible duplicate of {}:1: {}", b.display(),
+                a.display(), "2020-04-19 -20.00eur t groceries"),
+            format!(
+                "{}:2:1: possible duplicate of {}:1: {}", b.display(),
+                b.display(), "2020-04-19 -20.00eur t groceries"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn expression() {
+        let e = Entry::from_line("2020-04-19 -(12.50+3.20*2)eur t groceries")
+            .unwrap();
+        assert_eq!(e.value, Money::new(dec::Decimal::new(-1890, 2), EUR));
+        assert_eq!(e.to_line(), "2020-04-19 -18.90eur t groceries");
+        let e = Entry::from_line("2020-04-19 10*1.5jpy t x").unwrap();
+        assert_eq!(e.to_line(), "2020-04-19 15jpy t x");
+        let e = Entry::from_line("2020-04-19 -(12.50+3.20*2eur t x")
+            .unwrap_err();
+        assert_eq!(e.col, Some(26));
+        assert_eq!(
+            e.to_string(),
+            r#"column 26: invalid expression "-(12.50+3.20*2": expected ")""#);
+        assert_eq!(
+            &Entry::from_line("2020-04-19 10/3eur t x").unwrap_err().msg,
+            r#"too many decimal places in "10/3" for eur (maximum: 2)"#);
+    }
+
+    #[test]
+    fn serde() {
+        let e = Entry::from_line("2020-04-20 -100.00eur t description")
+            .unwrap();
+        let s = serde_json::to_string(&e).unwrap();
+        assert_eq!(s, concat!(
+            r#"{"date":"2020-04-20","#,
+            r#""value":{"amount":"-100.00","currency":"eur"},"#,
+            r#""tag":"t","text":"description"}"#));
+        assert_eq!(serde_json::from_str::<Entry>(&s).unwrap(), e);
+        let c = super::Currency {
+            name: USD,
+            to_eur: dec::Decimal::new(10837, 4),
+        };
+        let s = serde_json::to_string(&c).unwrap();
+        assert_eq!(s, r#"{"name":"usd","to_eur":"1.0837"}"#);
+        assert_eq!(serde_json::from_str::<super::Currency>(&s).unwrap(), c);
+    }
+
     #[test]
     fn to_line() {
         let e = Entry {
             date: chrono::NaiveDate::from_ymd(2020, 4, 20),
-            value: super::dec::Decimal::new(-100.0),
-            currency: EUR,
-            tag: b't',
+            value: Money::new(dec::Decimal::from(-100), EUR),
+            price: None,
+            account: None,
+            tag: tag("t"),
             text: String::from("description"),
+            hashtags: Vec::new(),
+            meta: std::collections::BTreeMap::new(),
+            transaction: None,
         };
         assert_eq!(e.to_line(), "2020-04-20 -100.00eur t description");
     }
 
+    #[test]
+    fn split() {
+        let e = Entry::from_line("2020-04-20 -100.00eur t dinner").unwrap();
+        let v = e.split(&[
+            (dec::Decimal::from(1), None),
+            (dec::Decimal::from(1), Some(tag("a"))),
+            (dec::Decimal::from(1), None),
+        ]).unwrap();
+        assert_eq!(
+            v.iter().map(Entry::to_line).collect::<Vec<_>>(), [
+                "2020-04-20 -33.34eur t dinner",
+                "2020-04-20 -33.33eur a dinner",
+                "2020-04-20 -33.33eur t dinner",
+            ]);
+        assert_eq!(e.split(&[]), None);
+    }
+
     #[test]
     fn unique_currencies() {
         let mut ret = Entry::unique_currencies(
@@ -320,10 +2085,14 @@ mod tests {
                 .iter()
                 .map(|&c| Entry {
                     date: chrono::NaiveDate::from_ymd(2020, 4, 20),
-                    value: super::dec::Decimal::new(0.0),
-                    currency: c,
-                    tag: b't',
+                    value: Money::zero(c),
+                    price: None,
+                    account: None,
+                    tag: tag("t"),
                     text: String::from("description"),
+                    hashtags: Vec::new(),
+                    meta: std::collections::BTreeMap::new(),
+                    transaction: None,
                 })
                 .collect::<Vec<Entry>>());
         ret.sort();
@@ -333,53 +2102,104 @@ mod tests {
     #[test]
     fn total() {
         let v: Vec<Entry> = [
-            (dec::Decimal::new(-100.0), EUR),
-            (dec::Decimal::new(-200.0), EUR),
-            (dec::Decimal::new( 300.0), USD),
-            (dec::Decimal::new(-400.0), USD),
-            (dec::Decimal::new( 500.0), EUR),
+            (dec::Decimal::from(-100), EUR),
+            (dec::Decimal::from(-200), EUR),
+            (dec::Decimal::from( 300), USD),
+            (dec::Decimal::from(-400), USD),
+            (dec::Decimal::from( 500), EUR),
         ].iter().map(|&(v, c)| Entry {
             date: chrono::NaiveDate::from_ymd(2020, 4, 20),
-            value: v,
-            currency: c,
-            tag: b't',
+            value: Money::new(v, c),
+            price: None,
+            account: None,
+            tag: tag("t"),
             text: String::from("description"),
+            hashtags: Vec::new(),
+            meta: std::collections::BTreeMap::new(),
+            transaction: None,
         }).collect();
-        let mut total = Entry::total(v.iter());
-        total.sort_by(|l, r| l.0.cmp(&r.0));
+        let mut total = Entry::total(v.iter()).unwrap();
+        total.sort_by_key(|x| x.0.currency);
         assert_eq!(total, vec![(
-            EUR,
-            dec::Decimal::new(500.0),
-            dec::Decimal::new(-300.0),
+            Money::new(dec::Decimal::from(500), EUR),
+            Money::new(dec::Decimal::from(-300), EUR),
         ), (
-            USD,
-            dec::Decimal::new(300.0),
-            dec::Decimal::new(-400.0),
+            Money::new(dec::Decimal::from(300), USD),
+            Money::new(dec::Decimal::from(-400), USD),
         )]);
     }
 
     #[test]
     fn total_with_conversion() {
         let v: Vec<Entry> = [
-            (dec::Decimal::new(-100.0), EUR),
-            (dec::Decimal::new(-200.0), EUR),
-            (dec::Decimal::new( 300.0), USD),
-            (dec::Decimal::new(-400.0), USD),
-            (dec::Decimal::new( 500.0), EUR),
+            (dec::Decimal::from(-100), EUR),
+            (dec::Decimal::from(-200), EUR),
+            (dec::Decimal::from( 300), USD),
+            (dec::Decimal::from(-400), USD),
+            (dec::Decimal::from( 500), EUR),
         ].iter().map(|&(v, c)| Entry {
             date: chrono::NaiveDate::from_ymd(2020, 4, 20),
-            value: v,
-            currency: c,
-            tag: b't',
+            value: Money::new(v, c),
+            price: None,
+            account: None,
+            tag: tag("t"),
             text: String::from("description"),
+            hashtags: Vec::new(),
+            meta: std::collections::BTreeMap::new(),
+            transaction: None,
         }).collect();
         let conv = [
-            (EUR, dec::Decimal::new(1.0)),
-            (USD, dec::Decimal::new(3.0)),
+            (EUR, dec::Decimal::from(1)),
+            (USD, dec::Decimal::from(3)),
+        ].iter().copied().collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(
+            Entry::total_with_conversion(
+                v.iter(), &conv, EUR, dec::Rounding::HalfEven),
+            Ok((
+                Money::new(dec::Decimal::from(1400), EUR),
+                Money::new(dec::Decimal::from(-1500), EUR),
+            )));
+        let conv = [
+            (EUR, dec::Decimal::from(1)),
+            (USD, dec::Decimal::new(100005, 5)),
         ].iter().copied().collect::<std::collections::HashMap<_, _>>();
-        assert_eq!(Entry::total_with_conversion(v.iter(), &conv), (
-            dec::Decimal::new(1400.0),
-            dec::Decimal::new(-1500.0),
-        ));
+        let v = &v[2..3];
+        assert_eq!(
+            Entry::total_with_conversion(
+                v.iter(), &conv, EUR, dec::Rounding::HalfEven),
+            Ok((
+                Money::new(dec::Decimal::new(30002, 2), EUR),
+                Money::zero(EUR),
+            )));
+        assert_eq!(
+            Entry::total_with_conversion(
+                v.iter(), &conv, EUR, dec::Rounding::Floor),
+            Ok((
+                Money::new(dec::Decimal::new(30001, 2), EUR),
+                Money::zero(EUR),
+            )));
+        assert_eq!(
+            Entry::total_with_conversion(
+                v.iter(), &std::collections::HashMap::new(), EUR,
+                dec::Rounding::HalfEven),
+            Err(money::Error::MissingRate(USD)));
+        let priced = |l| Entry::from_line(l).unwrap();
+        let v = [
+            priced("2020-04-20 -10.00usd@@9.50eur t x"),
+            priced("2020-04-20 10.00usd@0.5gbp t x"),
+            priced("2020-04-20 1.00usd t x"),
+        ];
+        let conv = [
+            (EUR, dec::Decimal::from(1)),
+            (USD, dec::Decimal::from(3)),
+            (GBP, dec::Decimal::from(2)),
+        ].iter().copied().collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(
+            Entry::total_with_conversion(
+                v.iter(), &conv, EUR, dec::Rounding::HalfEven),
+            Ok((
+                Money::new(dec::Decimal::from(13), EUR),
+                Money::new(dec::Decimal::new(-950, 2), EUR),
+            )));
     }
 }
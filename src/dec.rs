@@ -1,13 +1,287 @@
-#[derive(Copy, Debug, PartialEq, PartialOrd)]
+use std::convert::TryFrom;
+
+/// Number of decimal places kept in the result of a division.
+const DIV_SCALE: u32 = 16;
+
+/// Fixed-point decimal number: `mantissa * 10^-scale`.
+///
+/// Values with the same numeric value but different scales (e.g. `1.5` and
+/// `1.50`) compare and hash as equal.  The scale is preserved so that values
+/// are displayed with the same number of digits they were parsed with.
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Decimal {
-    // TODO implement a real decimal type
-    v: f64,
+    m: i128,
+    s: u32,
 }
 
 impl Decimal {
-    // XXX
-    pub fn new(v: f64) -> Decimal {
-        Decimal { v }
+    #[cfg(test)]
+    pub fn new(mantissa: i128, scale: u32) -> Decimal {
+        Decimal { m: mantissa, s: scale }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.s
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.m < 0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.m == 0
+    }
+
+    /// Removes trailing zeros from the fractional part.
+    pub fn normalize(self) -> Decimal {
+        let (mut m, mut s) = (self.m, self.s);
+        while s != 0 && m % 10 == 0 {
+            m /= 10;
+            s -= 1;
+        }
+        Decimal { m, s }
+    }
+
+    /// Mantissa of this value expressed with scale `s`, which must be greater
+    /// than or equal to the current scale.
+    fn checked_mantissa_at(&self, s: u32) -> Result<i128, Error> {
+        10i128.checked_pow(s - self.s)
+            .and_then(|x| self.m.checked_mul(x))
+            .ok_or(Error::Overflow)
+    }
+
+    pub fn checked_add(self, o: Decimal) -> Result<Decimal, Error> {
+        let s = self.s.max(o.s);
+        self.checked_mantissa_at(s)?
+            .checked_add(o.checked_mantissa_at(s)?)
+            .map(|m| Decimal { m, s })
+            .ok_or(Error::Overflow)
+    }
+
+    pub fn checked_sub(self, o: Decimal) -> Result<Decimal, Error> {
+        self.checked_add(-o)
+    }
+
+    pub fn checked_mul(self, o: Decimal) -> Result<Decimal, Error> {
+        let s = self.s.checked_add(o.s).ok_or(Error::Overflow)?;
+        self.m.checked_mul(o.m)
+            .map(|m| Decimal { m, s })
+            .ok_or(Error::Overflow)
+    }
+
+    /// Division is not exact in general: the result is rounded half away
+    /// from zero to `DIV_SCALE` decimal places and normalized.
+    pub fn checked_div(self, o: Decimal) -> Result<Decimal, Error> {
+        if o.m == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let e = i64::from(DIV_SCALE) + i64::from(o.s) - i64::from(self.s);
+        let scale = |m: i128, e: i64| u32::try_from(e).ok()
+            .and_then(|e| 10i128.checked_pow(e))
+            .and_then(|x| m.checked_mul(x))
+            .ok_or(Error::Overflow);
+        let (n, d) = if e >= 0 {
+            (scale(self.m, e)?, o.m)
+        } else {
+            (self.m, scale(o.m, -e)?)
+        };
+        Ok(Decimal { m: div_round(n, d, Rounding::HalfUp), s: DIV_SCALE }
+            .normalize())
+    }
+
+    /// Changes the scale to `s`, rounding according to `mode` if digits are
+    /// removed.  The value is returned unchanged if it cannot be represented
+    /// with `s` decimal places.
+    pub fn round(self, s: u32, mode: Rounding) -> Decimal {
+        if s >= self.s {
+            return match self.checked_mantissa_at(s) {
+                Ok(m) => Decimal { m, s },
+                Err(_) => self,
+            };
+        }
+        // A divisor which overflows is larger than any mantissa: only the
+        // sign of the value is left to round.
+        let m = match 10i128.checked_pow(self.s - s) {
+            Some(x) => div_round(self.m, x, mode),
+            None => div_round(self.m.signum(), i128::MAX, mode),
+        };
+        Decimal { m, s }
+    }
+
+    /// Splits this value into parts proportional to `ratios` with `scale`
+    /// decimal places.  The value is first rounded to `scale` (half-even) and
+    /// the parts always add up to it exactly: units left over after the
+    /// proportional division are distributed one by one to the parts with the
+    /// largest remainders, the first ones winning ties.
+    ///
+    /// Returns `None` if any ratio is negative or if they add up to zero.
+    pub fn allocate(
+        self,
+        ratios: &[Decimal],
+        scale: u32,
+    ) -> Option<Vec<Decimal>> {
+        if ratios.iter().any(Decimal::is_negative) {
+            return None;
+        }
+        let rs = ratios.iter().map(|x| x.s).max().unwrap_or(0);
+        let ratios: Vec<i128> = ratios
+            .iter()
+            .map(|x| x.checked_mantissa_at(rs).ok())
+            .collect::<Option<_>>()?;
+        let total = ratios.iter().try_fold(0i128, |t, x| t.checked_add(*x))?;
+        if total == 0 {
+            return None;
+        }
+        let v = self.round(scale, Rounding::HalfEven).m;
+        let (sign, v) = (v.signum(), v.abs());
+        let mut parts: Vec<(i128, i128)> = ratios
+            .iter()
+            .map(|r| v.checked_mul(*r).map(|x| (x / total, x % total)))
+            .collect::<Option<_>>()?;
+        let mut left = v - parts.iter().map(|x| x.0).sum::<i128>();
+        let mut order: Vec<usize> = (0..parts.len()).collect();
+        order.sort_by(|&l, &r| parts[r].1.cmp(&parts[l].1));
+        for i in order {
+            if left == 0 {
+                break;
+            }
+            parts[i].0 += 1;
+            left -= 1;
+        }
+        Some(parts
+            .iter()
+            .map(|x| Decimal { m: sign * x.0, s: scale })
+            .collect())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Overflow,
+    DivisionByZero,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Overflow => write!(f, "decimal overflow"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+/// Strategy used when digits have to be discarded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    /// Round to the nearest value, ties to the even neighbor.
+    HalfEven,
+    /// Round to the nearest value, ties away from zero.
+    HalfUp,
+    /// Round towards negative infinity.
+    Floor,
+    /// Round towards positive infinity.
+    Ceiling,
+    /// Round towards zero.
+    Truncate,
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "floor" => Ok(Rounding::Floor),
+            "ceiling" => Ok(Rounding::Ceiling),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(format!("invalid rounding mode: {}", s)),
+        }
+    }
+}
+
+/// Integer division rounding according to `mode`.
+fn div_round(n: i128, d: i128, mode: Rounding) -> i128 {
+    let (q, r) = (n / d, n % d);
+    if r == 0 {
+        return q;
+    }
+    // Direction away from zero, i.e. the sign of the exact result.
+    let away = if (n < 0) == (d < 0) { 1 } else { -1 };
+    let half = (2 * r.abs()).cmp(&d.abs());
+    let up = match mode {
+        Rounding::HalfEven =>
+            half == std::cmp::Ordering::Greater
+                || (half == std::cmp::Ordering::Equal && q % 2 != 0),
+        Rounding::HalfUp => half != std::cmp::Ordering::Less,
+        Rounding::Floor => away < 0,
+        Rounding::Ceiling => away > 0,
+        Rounding::Truncate => false,
+    };
+    if up { q + away } else { q }
+}
+
+/// Notation used to parse decimal numbers.
+///
+/// The default is the canonical notation accepted by `TryFrom<&str>`: a `.`
+/// decimal mark and no digit grouping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    pub decimal_mark: char,
+    pub group_mark: Option<char>,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format { decimal_mark: '.', group_mark: None }
+    }
+}
+
+impl Format {
+    /// Locale-style notation with the given decimal mark, where the other one
+    /// of `.` and `,` is accepted as a thousands separator.
+    pub fn with_decimal_mark(c: char) -> Option<Format> {
+        match c {
+            '.' => Some(Format { decimal_mark: '.', group_mark: Some(',') }),
+            ',' => Some(Format { decimal_mark: ',', group_mark: Some('.') }),
+            _ => None,
+        }
+    }
+
+    pub fn parse(&self, v: &str) -> Result<Decimal, ()> {
+        if *self == Format::default() {
+            return Decimal::try_from(v);
+        }
+        let (int, frac) = match v.find(self.decimal_mark) {
+            Some(i) => (&v[..i], Some(&v[i + self.decimal_mark.len_utf8()..])),
+            None => (v, None),
+        };
+        let mut s = String::with_capacity(v.len());
+        match self.group_mark {
+            Some(g) if int.contains(g) => {
+                let (sign, int) = match int.find(|c: char| c.is_ascii_digit()) {
+                    Some(i) => int.split_at(i),
+                    None => return Err(()),
+                };
+                s.push_str(sign);
+                for (i, x) in int.split(g).enumerate() {
+                    let valid = match i {
+                        0 => (1..=3).contains(&x.len()),
+                        _ => x.len() == 3,
+                    };
+                    if !valid || !x.bytes().all(|c| c.is_ascii_digit()) {
+                        return Err(());
+                    }
+                    s.push_str(x);
+                }
+            },
+            _ => s.push_str(int),
+        }
+        if let Some(frac) = frac {
+            s.push('.');
+            s.push_str(frac);
+        }
+        Decimal::try_from(s.as_str())
     }
 }
 
@@ -15,58 +289,326 @@ impl std::convert::TryFrom<&str> for Decimal {
     type Error = ();
 
     fn try_from(v: &str) -> Result<Self, Self::Error> {
-        match v.parse() {
-            Ok(v) => Ok(Decimal { v }),
-            Err(_) => Err(()),
+        let (neg, v) = match v.as_bytes().first() {
+            Some(b'-') => (true, &v[1..]),
+            Some(b'+') => (false, &v[1..]),
+            _ => (false, v),
+        };
+        let (int, frac) = match v.find('.') {
+            Some(i) => (&v[..i], &v[i + 1..]),
+            None => (v, ""),
+        };
+        if int.is_empty() && frac.is_empty() {
+            return Err(());
+        }
+        let mut m: i128 = 0;
+        for c in int.bytes().chain(frac.bytes()) {
+            if !c.is_ascii_digit() {
+                return Err(());
+            }
+            m = m.checked_mul(10)
+                .and_then(|m| m.checked_add(i128::from(c - b'0')))
+                .ok_or(())?;
         }
+        Ok(Decimal {
+            m: if neg { -m } else { m },
+            s: frac.len() as u32,
+        })
     }
 }
 
-impl Clone for Decimal {
-    fn clone(&self) -> Decimal {
-        Decimal { v: self.v }
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let d = match f.precision() {
+            Some(p) => self.round(p as u32, Rounding::HalfUp),
+            None => *self,
+        };
+        let digits = d.m.unsigned_abs().to_string();
+        let s = d.s as usize;
+        let (int, frac) = if digits.len() > s {
+            digits.split_at(digits.len() - s)
+        } else {
+            ("0", digits.as_str())
+        };
+        let sign = if d.m < 0 { "-" } else { "" };
+        if s == 0 {
+            write!(f, "{}{}", sign, int)
+        } else {
+            write!(f, "{}{}.{:0>3$}", sign, int, frac, s)
+        }
     }
 }
 
-impl std::fmt::Display for Decimal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match f.precision() {
-            Some(p) => write!(f, "{1:.*}", p, self.v),
-            None => write!(f, "{}", self.v),
+impl PartialEq for Decimal {
+    fn eq(&self, o: &Decimal) -> bool {
+        self.cmp(o) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, o: &Decimal) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(o))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, o: &Decimal) -> std::cmp::Ordering {
+        let s = self.s.max(o.s);
+        // A mantissa which overflows at the common scale has a larger
+        // magnitude than the other one, which fits.
+        match (self.checked_mantissa_at(s), o.checked_mantissa_at(s)) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Err(_), _) => self.m.cmp(&0),
+            (_, Err(_)) => 0.cmp(&o.m),
         }
     }
 }
 
+impl std::hash::Hash for Decimal {
+    fn hash<H: std::hash::Hasher>(&self, h: &mut H) {
+        let d = self.normalize();
+        d.m.hash(h);
+        d.s.hash(h);
+    }
+}
+
+/// Serialized as a string with all digits, so that no precision is lost.
+impl serde::Serialize for Decimal {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D>(d: D) -> Result<Decimal, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        Decimal::try_from(s.as_str()).map_err(|_|
+            serde::de::Error::custom(format!(r#"invalid decimal "{}""#, s)))
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(v: i64) -> Decimal {
+        Decimal { m: i128::from(v), s: 0 }
+    }
+}
+
+impl core::ops::Neg for Decimal {
+    type Output = Decimal;
+    fn neg(self) -> Self::Output {
+        Decimal { m: -self.m, s: self.s }
+    }
+}
+
+/// The arithmetic operators panic on overflow and division by zero, the
+/// `checked_*` functions should be used for untrusted values.
 impl core::ops::Add for Decimal {
     type Output = Decimal;
     fn add(self, o: Decimal) -> Self::Output {
-        Decimal { v: self.v + o.v }
+        self.checked_add(o).unwrap()
     }
 }
 
 impl core::ops::Sub for Decimal {
     type Output = Decimal;
     fn sub(self, o: Decimal) -> Self::Output {
-        Decimal { v: self.v - o.v }
+        self.checked_sub(o).unwrap()
     }
 }
 
 impl core::ops::AddAssign for Decimal {
     fn add_assign(&mut self, o: Decimal) {
-        self.v += o.v
+        *self = *self + o
     }
 }
 
 impl core::ops::Mul for Decimal {
     type Output = Decimal;
     fn mul(self, o: Decimal) -> Self::Output {
-        Decimal { v: self.v * o.v }
+        self.checked_mul(o).unwrap()
     }
 }
 
 impl core::ops::Div for Decimal {
     type Output = Decimal;
     fn div(self, o: Decimal) -> Self::Output {
-        Decimal { v: self.v / o.v }
+        self.checked_div(o).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::Decimal;
+    use super::Rounding;
+
+    fn d(s: &str) -> Decimal {
+        Decimal::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(d("123.45"), Decimal::new(12345, 2));
+        assert_eq!(d("-0.05"), Decimal::new(-5, 2));
+        assert_eq!(d("+7"), Decimal::from(7));
+        assert_eq!(d(".5"), Decimal::new(5, 1));
+        assert_eq!(d("1.50").scale(), 2);
+        for x in &["", "-", ".", "1.2.3", "1e5", "inf", "nan", "1,00", " 1"] {
+            assert_eq!(Decimal::try_from(*x), Err(()), "{}", x);
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(d("123.45").to_string(), "123.45");
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("1.50").to_string(), "1.50");
+        assert_eq!(format!("{:.2}", d("-100")), "-100.00");
+        assert_eq!(format!("{:.2}", d("0.125")), "0.13");
+        assert_eq!(format!("{:.2}", d("-0.125")), "-0.13");
+        assert_eq!(format!("{:.0}", d("2.5")), "3");
+        assert_eq!(format!("{:.3}", d("0.0001")), "0.000");
+    }
+
+    #[test]
+    fn eq() {
+        assert_eq!(d("1.5"), d("1.500"));
+        assert!(d("1.05") < d("1.5"));
+        assert!(d("-2") < d("-1.99"));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(d("0.1") + d("0.2"), d("0.3"));
+        assert_eq!(d("0.3") - d("0.1"), d("0.2"));
+        assert_eq!(d("1.10") * d("1.1"), d("1.21"));
+        assert_eq!((d("1.10") * d("1.1")).scale(), 3);
+        assert_eq!(d("1") / d("4"), d("0.25"));
+        assert_eq!((d("1") / d("4")).scale(), 2);
+        assert_eq!(d("2") / d("3"), d("0.6666666666666667"));
+        assert_eq!(d("-2") / d("3"), d("-0.6666666666666667"));
+        let mut sum = Decimal::default();
+        for _ in 0..10 {
+            sum += d("0.1");
+        }
+        assert_eq!(sum, Decimal::from(1));
+    }
+
+    #[test]
+    fn round() {
+        let cases = [
+            ("2.5", ["2", "3", "2", "3", "2"]),
+            ("3.5", ["4", "4", "3", "4", "3"]),
+            ("-2.5", ["-2", "-3", "-3", "-2", "-2"]),
+            ("2.51", ["3", "3", "2", "3", "2"]),
+            ("-2.49", ["-2", "-2", "-3", "-2", "-2"]),
+            ("7", ["7", "7", "7", "7", "7"]),
+        ];
+        let modes = [
+            Rounding::HalfEven, Rounding::HalfUp,
+            Rounding::Floor, Rounding::Ceiling, Rounding::Truncate,
+        ];
+        for (v, expected) in &cases {
+            for (mode, e) in modes.iter().zip(expected.iter()) {
+                let r = d(v).round(0, *mode);
+                assert_eq!(r.to_string(), *e, "{} {:?}", v, mode);
+            }
+        }
+        assert_eq!(d("1.005").round(2, Rounding::HalfEven).to_string(), "1.00");
+        assert_eq!(d("1.015").round(2, Rounding::HalfEven).to_string(), "1.02");
+        assert_eq!(d("1.5").round(3, Rounding::Floor).to_string(), "1.500");
+        let max = "99999999999999999999999999999999999999";
+        assert_eq!(d(max).round(2, Rounding::HalfEven).to_string(), max);
+        assert_eq!(format!("{:.2}", d(max)), max);
+        let tiny = Decimal::new(-1, 40);
+        assert_eq!(tiny.round(0, Rounding::HalfEven).to_string(), "0");
+        assert_eq!(tiny.round(0, Rounding::Floor).to_string(), "-1");
+        assert_eq!("half-even".parse(), Ok(Rounding::HalfEven));
+        assert!("nearest".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    fn allocate() {
+        let parts = |v: &str, r: &[&str], s| d(v)
+            .allocate(&r.iter().map(|x| d(x)).collect::<Vec<_>>(), s)
+            .map(|v| v.iter().map(Decimal::to_string).collect::<Vec<_>>());
+        assert_eq!(
+            parts("100.00", &["1", "1", "1"], 2).unwrap(),
+            ["33.34", "33.33", "33.33"]);
+        assert_eq!(
+            parts("-100.00", &["1", "1", "1"], 2).unwrap(),
+            ["-33.34", "-33.33", "-33.33"]);
+        assert_eq!(
+            parts("0.05", &["3", "7"], 2).unwrap(),
+            ["0.02", "0.03"]);
+        assert_eq!(
+            parts("10", &["33.3", "33.3", "33.4"], 2).unwrap(),
+            ["3.33", "3.33", "3.34"]);
+        assert_eq!(parts("1000", &["1", "2"], 0).unwrap(), ["333", "667"]);
+        assert_eq!(parts("1", &["0", "1"], 2).unwrap(), ["0.00", "1.00"]);
+        assert_eq!(parts("1", &["0", "0"], 2), None);
+        assert_eq!(parts("1", &["-1", "2"], 2), None);
+    }
+
+    #[test]
+    fn format() {
+        use super::Format;
+        let parse = |f: &Format, s| f.parse(s).map(|x| x.to_string());
+        let f = Format::default();
+        assert_eq!(parse(&f, "1234.56"), Ok(String::from("1234.56")));
+        assert_eq!(parse(&f, "1,234.56"), Err(()));
+        let f = Format::with_decimal_mark('.').unwrap();
+        assert_eq!(parse(&f, "1234.56"), Ok(String::from("1234.56")));
+        assert_eq!(parse(&f, "1,234.56"), Ok(String::from("1234.56")));
+        assert_eq!(parse(&f, "-1,234,567"), Ok(String::from("-1234567")));
+        assert_eq!(parse(&f, "1,23.56"), Err(()));
+        assert_eq!(parse(&f, "1234,567.00"), Err(()));
+        assert_eq!(parse(&f, ",123"), Err(()));
+        let f = Format::with_decimal_mark(',').unwrap();
+        assert_eq!(parse(&f, "1.234,56"), Ok(String::from("1234.56")));
+        assert_eq!(parse(&f, "-0,5"), Ok(String::from("-0.5")));
+        assert_eq!(parse(&f, "1234,56"), Ok(String::from("1234.56")));
+        assert_eq!(parse(&f, "1.234.56"), Err(()));
+        assert_eq!(parse(&f, "1,234,56"), Err(()));
+        assert_eq!(Format::with_decimal_mark('x'), None);
+    }
+
+    #[test]
+    fn checked() {
+        use super::Error;
+        let max = Decimal::new(i128::MAX, 0);
+        assert_eq!(d("1.5").checked_add(d("2")), Ok(d("3.5")));
+        assert_eq!(max.checked_add(d("1")), Err(Error::Overflow));
+        assert_eq!(max.checked_add(d("0.1")), Err(Error::Overflow));
+        assert_eq!((-max).checked_sub(d("2")), Err(Error::Overflow));
+        assert_eq!(d("1.5").checked_mul(d("2")), Ok(d("3")));
+        assert_eq!(max.checked_mul(d("2")), Err(Error::Overflow));
+        assert_eq!(d("1").checked_div(d("8")), Ok(d("0.125")));
+        assert_eq!(d("1").checked_div(d("0.00")), Err(Error::DivisionByZero));
+        assert_eq!(max.checked_div(d("0.5")), Err(Error::Overflow));
+        assert!(max > d("0.5"));
+        assert!(d("0.5") < max);
+        assert!(-max < d("-0.5"));
+        assert!(d("-0.5") > -max);
+    }
+
+    #[test]
+    fn serde() {
+        let s = serde_json::to_string(&d("-0.050")).unwrap();
+        assert_eq!(s, r#""-0.050""#);
+        let v: Decimal = serde_json::from_str(r#""12345678901234567890.12""#)
+            .unwrap();
+        assert_eq!(v, Decimal::new(1234567890123456789012, 2));
+        assert!(serde_json::from_str::<Decimal>(r#""1e5""#).is_err());
+        assert!(serde_json::from_str::<Decimal>("1.5").is_err());
     }
 }
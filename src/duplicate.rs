@@ -0,0 +1,105 @@
+use super::db;
+
+/// Metadata which marks an entry as a legitimate repeat, e.g. a second
+/// identical coffee on the same day: `duplicate=ok`.
+pub const ALLOW_KEY: &str = "duplicate";
+pub const ALLOW_VALUE: &str = "ok";
+
+/// Two entries which are likely the same, identified by their indices.
+#[derive(Debug, PartialEq)]
+pub struct Pair {
+    pub first: usize,
+    pub second: usize,
+    /// Whether the entries are identical, as opposed to only similar.
+    pub exact: bool,
+}
+
+/// Finds exact duplicates and near-duplicates: entries with the same value
+/// at most `days` days apart and similar descriptions.  Postings of the same
+/// transaction and entries marked with `duplicate=ok` are not reported.
+/// Pairs are sorted by the second entry.
+pub fn find(v: &[db::Entry], days: i64) -> Vec<Pair> {
+    let allowed = |x: &db::Entry|
+        x.meta.get(ALLOW_KEY).map(String::as_str) == Some(ALLOW_VALUE);
+    let mut idx: Vec<_> = (0..v.len()).filter(|&i| !allowed(&v[i])).collect();
+    idx.sort_by_key(|&i| (v[i].value.currency, v[i].value.amount, v[i].date));
+    let mut ret = Vec::new();
+    for (n, &i) in idx.iter().enumerate() {
+        let a = &v[i];
+        for &j in &idx[n + 1..] {
+            let b = &v[j];
+            if b.value != a.value || (b.date - a.date).num_days() > days {
+                break;
+            }
+            if a.transaction.is_some() && a.transaction == b.transaction {
+                continue;
+            }
+            let exact = a.to_line() == b.to_line();
+            if exact || similar(&a.text, &b.text) {
+                let (first, second) = (i.min(j), i.max(j));
+                ret.push(Pair { first, second, exact });
+            }
+        }
+    }
+    ret.sort_by_key(|x| (x.second, x.first));
+    ret
+}
+
+/// Whether two descriptions share at least half of their words, ignoring
+/// case.
+fn similar(a: &str, b: &str) -> bool {
+    let words = |s: &str| s.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<std::collections::BTreeSet<_>>();
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    union == 0 || 2 * a.intersection(&b).count() >= union
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::Pair;
+
+    fn read(v: &[&str]) -> Vec<db::Entry> {
+        v.iter().map(|x| db::Entry::from_line(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn similar() {
+        assert!(super::similar("", ""));
+        assert!(super::similar("Supermarket", "supermarket"));
+        assert!(super::similar("supermarket lisbon", "supermarket"));
+        assert!(!super::similar("supermarket", "bakery"));
+        assert!(!super::similar("a b c", "a"));
+    }
+
+    #[test]
+    fn find() {
+        let v = read(&[
+            "2020-04-19 -20.00eur t supermarket",
+            "2020-04-19 -2.00eur t coffee",
+            "2020-04-19 -20.00eur t supermarket",
+            "2020-04-21 -20.00eur food Supermarket Lisbon",
+            "2020-04-30 -20.00eur t supermarket",
+            "2020-04-19 -2.00eur t coffee duplicate=ok",
+            "2020-04-19 -20.00eur t bakery",
+            "2020-04-19 -20.00usd t supermarket",
+        ]);
+        assert_eq!(super::find(&v, 3), [
+            Pair { first: 0, second: 2, exact: true },
+            Pair { first: 0, second: 3, exact: false },
+            Pair { first: 2, second: 3, exact: false },
+        ]);
+        assert_eq!(super::find(&v, 0), [
+            Pair { first: 0, second: 2, exact: true },
+        ]);
+        let mut v = read(&[
+            "2020-04-19 -1.00eur t a",
+            "2020-04-19 -1.00eur t a",
+        ]);
+        v[0].transaction = Some(0);
+        v[1].transaction = Some(0);
+        assert_eq!(super::find(&v, 3), []);
+    }
+}
@@ -0,0 +1,175 @@
+use super::dec;
+
+/// Error in an arithmetic expression, `pos` is the byte offset in the input
+/// where it was detected.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub pos: usize,
+    pub msg: String,
+}
+
+/// Evaluates an arithmetic expression with `+`, `-`, `*`, `/` and
+/// parentheses, e.g. `-(12.50+3.20*2)`.  Numbers are parsed with `format`.
+///
+/// Evaluation is exact except for division, see `dec::Decimal`.
+pub fn eval(s: &str, format: &dec::Format) -> Result<dec::Decimal, Error> {
+    let mut p = Parser { s, pos: 0, format };
+    let ret = p.expr()?;
+    match p.peek() {
+        None => Ok(ret),
+        Some(c) => Err(p.error(format!(r#"unexpected "{}""#, c))),
+    }
+}
+
+fn error(pos: usize, e: dec::Error) -> Error {
+    Error { pos, msg: e.to_string() }
+}
+
+/// Recursive-descent parser:
+///
+/// ```text
+/// expr   := term (('+' | '-') term)*
+/// term   := factor (('*' | '/') factor)*
+/// factor := ('+' | '-') factor | '(' expr ')' | number
+/// ```
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    format: &'a dec::Format,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn error(&self, msg: String) -> Error {
+        Error { pos: self.pos, msg }
+    }
+
+    /// Skips an operator, returns its position.
+    fn next(&mut self) -> usize {
+        self.pos += 1;
+        self.pos - 1
+    }
+
+    fn expr(&mut self) -> Result<dec::Decimal, Error> {
+        let mut ret = self.term()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    let pos = self.next();
+                    let r = self.term()?;
+                    ret = ret.checked_add(r).map_err(|e| error(pos, e))?;
+                },
+                Some('-') => {
+                    let pos = self.next();
+                    let r = self.term()?;
+                    ret = ret.checked_sub(r).map_err(|e| error(pos, e))?;
+                },
+                _ => return Ok(ret),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<dec::Decimal, Error> {
+        let mut ret = self.factor()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    let pos = self.next();
+                    let r = self.factor()?;
+                    ret = ret.checked_mul(r).map_err(|e| error(pos, e))?;
+                },
+                Some('/') => {
+                    let pos = self.next();
+                    let r = self.factor()?;
+                    ret = ret.checked_div(r).map_err(|e| error(pos, e))?;
+                },
+                _ => return Ok(ret),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<dec::Decimal, Error> {
+        match self.peek() {
+            Some('+') => { self.pos += 1; self.factor() },
+            Some('-') => { self.pos += 1; Ok(-self.factor()?) },
+            Some('(') => {
+                self.pos += 1;
+                let ret = self.expr()?;
+                match self.peek() {
+                    Some(')') => { self.pos += 1; Ok(ret) },
+                    _ => Err(self.error(String::from(r#"expected ")""#))),
+                }
+            },
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<dec::Decimal, Error> {
+        let format = self.format;
+        let len = self.s[self.pos..]
+            .find(|c: char|
+                !c.is_ascii_digit()
+                    && c != format.decimal_mark
+                    && Some(c) != format.group_mark)
+            .unwrap_or(self.s.len() - self.pos);
+        if len == 0 {
+            return Err(self.error(match self.peek() {
+                Some(c) => format!(r#"expected number, found "{}""#, c),
+                None => String::from("expected number"),
+            }));
+        }
+        let n = &self.s[self.pos..self.pos + len];
+        let ret = format.parse(n)
+            .map_err(|_| self.error(format!(r#"invalid number "{}""#, n)))?;
+        self.pos += len;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dec;
+    use super::Error;
+
+    fn eval(s: &str) -> Result<String, Error> {
+        super::eval(s, &dec::Format::default()).map(|x| x.to_string())
+    }
+
+    #[test]
+    fn eval_ok() {
+        assert_eq!(eval("12.50"), Ok(String::from("12.50")));
+        assert_eq!(eval("-12.50"), Ok(String::from("-12.50")));
+        assert_eq!(eval("1+2*3"), Ok(String::from("7")));
+        assert_eq!(eval("(1+2)*3"), Ok(String::from("9")));
+        assert_eq!(eval("-(12.50+3.20*2)"), Ok(String::from("-18.90")));
+        assert_eq!(eval("10-2-3"), Ok(String::from("5")));
+        assert_eq!(eval("12/4/3"), Ok(String::from("1")));
+        assert_eq!(eval("--1"), Ok(String::from("1")));
+        assert_eq!(eval("0.1+0.2"), Ok(String::from("0.3")));
+    }
+
+    #[test]
+    fn eval_format() {
+        let f = dec::Format::with_decimal_mark(',').unwrap();
+        assert_eq!(
+            super::eval("1.000,50+2*0,25", &f).map(|x| x.to_string()),
+            Ok(String::from("1001.00")));
+    }
+
+    #[test]
+    fn eval_error() {
+        let err = |pos, msg: &str| Err(Error { pos, msg: String::from(msg) });
+        assert_eq!(eval(""), err(0, "expected number"));
+        assert_eq!(eval("(1+2"), err(4, r#"expected ")""#));
+        assert_eq!(eval("1+*2"), err(2, r#"expected number, found "*""#));
+        assert_eq!(eval("1+2)"), err(3, r#"unexpected ")""#));
+        assert_eq!(eval("1/(2-2)"), err(1, "division by zero"));
+        assert_eq!(
+            eval("100000000000000000000*100000000000000000000"),
+            err(21, "decimal overflow"));
+        assert_eq!(eval("1+2.3.4"), err(2, r#"invalid number "2.3.4""#));
+    }
+}
@@ -0,0 +1,129 @@
+use super::db;
+
+/// Condition used to select entries.
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    /// `#name`: entries with a hashtag.
+    Hashtag(String),
+    /// `key=value`: entries with a metadata value.
+    Meta(String, String),
+}
+
+impl Filter {
+    pub fn matches(&self, e: &db::Entry) -> bool {
+        match self {
+            Filter::Hashtag(t) => e.hashtags.contains(t),
+            Filter::Meta(k, v) => e.meta.get(k) == Some(v),
+        }
+    }
+
+    pub fn matches_all(v: &[Filter], e: &db::Entry) -> bool {
+        v.iter().all(|x| x.matches(e))
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(t) = s.strip_prefix('#').filter(|x| !x.is_empty()) {
+            return Ok(Filter::Hashtag(String::from(t)));
+        }
+        match s.find('=') {
+            Some(i) if i != 0 => Ok(Filter::Meta(
+                String::from(&s[..i]), String::from(&s[i + 1..]))),
+            _ => Err(format!(r#"invalid filter "{}""#, s)),
+        }
+    }
+}
+
+/// Key used to group entries in reports.
+#[derive(Debug, PartialEq)]
+pub enum Group {
+    /// Tags, optionally rolled up to a maximum depth.
+    Tag(Option<usize>),
+    /// Hashtags, an entry is part of the group of each of its hashtags.
+    Hashtag,
+    /// Values of a metadata key.
+    Meta(String),
+}
+
+impl Group {
+    /// Names of the groups an entry belongs to.  Entries without hashtags or
+    /// without the metadata key are grouped under `-`.
+    pub fn keys(&self, e: &db::Entry) -> Vec<String> {
+        let none = || vec![String::from("-")];
+        match self {
+            Group::Tag(None) => vec![e.tag.to_string()],
+            Group::Tag(Some(n)) => vec![e.tag.truncate(*n).to_string()],
+            Group::Hashtag if e.hashtags.is_empty() => none(),
+            Group::Hashtag => e.hashtags.iter()
+                .map(|x| format!("#{}", x))
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            Group::Meta(k) => match e.meta.get(k) {
+                Some(v) => vec![format!("{}={}", k, v)],
+                None => none(),
+            },
+        }
+    }
+}
+
+/// Parses `<depth>` (tag depth), `#` (hashtags) or `<key>=` (metadata).
+impl std::str::FromStr for Group {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "#" {
+            return Ok(Group::Hashtag);
+        }
+        if let Some(k) = s.strip_suffix('=').filter(|x| !x.is_empty()) {
+            return Ok(Group::Meta(String::from(k)));
+        }
+        s.parse()
+            .map(|n| Group::Tag(Some(n)))
+            .map_err(|_| format!(r#"invalid group "{}""#, s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::Filter;
+    use super::Group;
+
+    #[test]
+    fn filter() {
+        let e = db::Entry::from_line(
+            "2020-04-20 -10.00eur t dinner #trip receipt=123").unwrap();
+        let f = |s: &str| s.parse::<Filter>().unwrap();
+        assert!(f("#trip").matches(&e));
+        assert!(!f("#tri").matches(&e));
+        assert!(f("receipt=123").matches(&e));
+        assert!(!f("receipt=12").matches(&e));
+        assert!(Filter::matches_all(&[f("#trip"), f("receipt=123")], &e));
+        assert!(!Filter::matches_all(&[f("#trip"), f("receipt=1")], &e));
+        for x in &["", "#", "=x", "trip"] {
+            assert!(x.parse::<Filter>().is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn group() {
+        let e = db::Entry::from_line(
+            "2020-04-20 -10.00eur food:out dinner #trip #work receipt=123")
+            .unwrap();
+        let g = |s: &str| s.parse::<Group>().unwrap();
+        assert_eq!(Group::Tag(None).keys(&e), ["food:out"]);
+        assert_eq!(g("1").keys(&e), ["food"]);
+        assert_eq!(g("#").keys(&e), ["#trip", "#work"]);
+        assert_eq!(g("receipt=").keys(&e), ["receipt=123"]);
+        assert_eq!(g("x=").keys(&e), ["-"]);
+        let e = db::Entry::from_line(
+            "2020-04-20 -10.00eur t #work taxi #trip #work").unwrap();
+        assert_eq!(g("#").keys(&e), ["#trip", "#work"]);
+        assert!("=".parse::<Group>().is_err());
+        assert!("x".parse::<Group>().is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use super::db;
+
+/// Name of the file in the database directory which enables or disables
+/// each rule.
+pub const FILE_NAME: &str = "lint";
+
+/// Style rule verified by `check --lint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    /// Dates must not decrease, within and across files.
+    Order,
+    /// Dates must not be after today.
+    Future,
+    /// Amounts must be written as they are printed, e.g. `-2.00eur`.
+    Amount,
+    /// No consecutive spaces after the indentation.
+    Spaces,
+    /// Currencies must be lowercase.
+    Currency,
+    /// No whitespace at the end of lines.
+    Trailing,
+}
+
+const RULES: [(Rule, &str); 6] = [
+    (Rule::Order, "order"),
+    (Rule::Future, "future"),
+    (Rule::Amount, "canonical-amount"),
+    (Rule::Spaces, "double-space"),
+    (Rule::Currency, "uppercase-currency"),
+    (Rule::Trailing, "trailing-whitespace"),
+];
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RULES.iter()
+            .find(|(_, name)| *name == s)
+            .map(|(r, _)| *r)
+            .ok_or_else(|| format!(r#"invalid rule "{}""#, s))
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = RULES.iter().find(|(r, _)| r == self).unwrap().1;
+        f.write_str(name)
+    }
+}
+
+/// Rules which are disabled, all are enabled by default.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    disabled: std::collections::BTreeSet<Rule>,
+}
+
+impl Config {
+    pub fn enabled(&self, r: Rule) -> bool {
+        !self.disabled.contains(&r)
+    }
+}
+
+/// Reads the configuration in a file with one `<rule> <on|off>` line per
+/// rule (see `db::read_config`).  An empty file enables all rules.
+pub fn read(path: &std::path::Path) -> Result<Config, String> {
+    db::read_config(path, parse)
+}
+
+/// Parses the configuration.
+fn parse(s: &str) -> Result<Config, (usize, db::EntryParseError)> {
+    let mut ret = Config::default();
+    db::parse_config(s, |l| {
+        let err = db::EntryParseError::new;
+        let l = l.trim_start();
+        let mut fields = l.split(' ');
+        let rule = fields.next().unwrap_or_default().parse().map_err(err)?;
+        match (fields.next(), fields.next()) {
+            (Some("on"), None) => ret.disabled.remove(&rule),
+            (Some("off"), None) => ret.disabled.insert(rule),
+            _ => return Err(err(format!(r#"invalid setting "{}""#, l))),
+        };
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+/// Violation of a rule at a line of a database file.  Columns start at 1.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub path: std::path::PathBuf,
+    pub line: usize,
+    pub col: usize,
+    pub rule: Rule,
+    pub msg: String,
+}
+
+/// Formats warnings as `<file>:<line>:<col>: <message> [<rule>]`.
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "{}:{}:{}: {} [{}]",
+            self.path.display(), self.line, self.col, self.msg, self.rule)
+    }
+}
+
+/// Verifies the enabled rules on the entries read with
+/// `db::Entry::read_db_located` and on every line of `files`, all the files
+/// of the database (see `db::Entry::read_db_files`), which are read with
+/// `opts`.  Warnings are sorted by file, in the order they were read, and
+/// line.
+pub fn check(
+    v: &[(db::Entry, db::Location)],
+    files: &[std::path::PathBuf],
+    opts: &db::Options,
+    config: &Config,
+    today: chrono::NaiveDate,
+) -> Result<Vec<Warning>, String> {
+    let files = files.iter()
+        .map(|p| std::fs::read_to_string(p)
+            .map(|s| (p.clone(), s))
+            .map_err(|e| format!("{}: {}", p.display(), e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lint(v, &files, opts, config, today))
+}
+
+/// Implementation of `check`, with the contents of each file.
+fn lint(
+    v: &[(db::Entry, db::Location)],
+    files: &[(std::path::PathBuf, String)],
+    opts: &db::Options,
+    config: &Config,
+    today: chrono::NaiveDate,
+) -> Vec<Warning> {
+    let mut ret = Vec::new();
+    let mut warn = |loc: &db::Location, col, rule, msg| {
+        if config.enabled(rule) {
+            let path = loc.path.clone();
+            ret.push(Warning { path, line: loc.line, col, rule, msg });
+        }
+    };
+    // Decimal mark in effect at each line of each file.
+    let mut marks = std::collections::HashMap::new();
+    for (path, s) in files {
+        let mut opts = opts.clone();
+        for (i, l) in s.lines().enumerate() {
+            if l.is_empty() && opts.blank_lines == db::BlankLines::Stop {
+                break;
+            }
+            let loc =
+                db::Location { path: path.clone(), line: i + 1, col: 1 };
+            let content = l.trim_end();
+            if content.len() != l.len() {
+                warn(&loc, content.len() + 1, Rule::Trailing,
+                    String::from("trailing whitespace"));
+            }
+            let indent = l.len() - l.trim_start().len();
+            let code = db::strip_comment(content);
+            if let Some(i) = code.get(indent..).and_then(|x| x.find("  ")) {
+                warn(&loc, indent + i + 1, Rule::Spaces,
+                    String::from("consecutive spaces"));
+            }
+            if code.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                // Invalid directives are reported by `check` itself.
+                let _ = opts.apply_directive(code);
+            }
+            marks.insert((path, i + 1), opts.format.decimal_mark);
+        }
+    }
+    let mut latest: Option<&(db::Entry, db::Location)> = None;
+    for x in v {
+        let (e, loc) = x;
+        match latest {
+            Some((p, ploc)) if e.date < p.date => warn(loc, 1, Rule::Order,
+                format!(
+                    "date {} is earlier than {} at {}:{}",
+                    e.date, p.date, ploc.path.display(), ploc.line)),
+            _ => latest = Some(x),
+        }
+        if e.date > today {
+            warn(loc, 1, Rule::Future,
+                format!("date {} is in the future", e.date));
+        }
+        let l = files.iter()
+            .find(|(p, _)| *p == loc.path)
+            .and_then(|(_, s)| s.lines().nth(loc.line - 1))
+            .unwrap_or_default();
+        let (col, amount) = amount_field(l, e.transaction.is_some());
+        let currencies = std::iter::once(e.value.currency)
+            .chain(e.price.map(|p| match p {
+                super::money::Price::Unit(x)
+                    | super::money::Price::Total(x) => x.currency,
+            }));
+        for c in currencies {
+            if c.as_str().bytes().any(|x| x.is_ascii_uppercase()) {
+                warn(loc, col, Rule::Currency,
+                    format!(r#"uppercase currency "{}""#, c));
+            }
+        }
+        let value = &amount[..amount.find('@').unwrap_or(amount.len())];
+        let mark = marks.get(&(&loc.path, loc.line)).copied().unwrap_or('.');
+        let canonical = format!(
+            "{}{}",
+            e.value,
+            e.price.map_or_else(String::new, |x| x.to_string()))
+            .replace('.', &mark.to_string());
+        if !db::Entry::is_expression(value) && amount != canonical {
+            warn(loc, col, Rule::Amount,
+                format!(r#"amount "{}" should be "{}""#, amount, canonical));
+        }
+    }
+    let order = |p: &std::path::Path|
+        files.iter().position(|(x, _)| x == p);
+    ret.sort_by_key(|x| (order(&x.path), x.line, x.col));
+    ret
+}
+
+/// Column and text of the amount of an entry (after the date) or of a
+/// posting (after the indentation).
+fn amount_field(l: &str, posting: bool) -> (usize, &str) {
+    let rest = l.trim_start();
+    let rest = if posting {
+        rest
+    } else {
+        rest.split_once(' ').map_or("", |x| x.1)
+    };
+    let col = l.len() - rest.len() + 1;
+    (col, rest.split(' ').next().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::Config;
+    use super::Rule;
+
+    fn date(s: &str) -> chrono::NaiveDate {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let c = super::parse("\
+# defaults
+future off
+order off
+order on
+").unwrap();
+        assert!(!c.enabled(Rule::Future));
+        assert!(c.enabled(Rule::Order));
+        assert!(c.enabled(Rule::Trailing));
+        assert_eq!(Rule::Amount.to_string(), "canonical-amount");
+        for (s, l) in &[
+            ("order\n", 1),
+            ("\norder yes\n", 2),
+            ("order off x\n", 1),
+            ("sorting off\n", 1),
+        ] {
+            assert_eq!(super::parse(s).unwrap_err().0, *l, "{}", s);
+        }
+    }
+
+    #[test]
+    fn lint() -> std::io::Result<()> {
+        let dir = db::TempDir::new("lint")?;
+        dir.write("db/a.txt", "\
+2020-04-19 -20.00eur t groceries
+2020-04-18 -2eur t coffee  ; two  spaces in a comment
+2020-04-20 -(1+1)eur t x \n\
+2020-04-20  shop
+  -1.00EUR t a
+  1.00eur@1.5usd t b
+")?;
+        dir.write("db/b.txt", "\
+2020-04-19 -1.00eur t x
+2021-01-01 -1.00eur t later
+")?;
+        dir.write("db/c.txt", "include ../shared.txt\n")?;
+        dir.write("shared.txt", "; shared \n")?;
+        dir.write("db/d.txt", "\
+2021-01-01 -2.00eur t a
+decimal-mark ,
+2021-01-01 -2,00eur t a
+2021-01-01 -2.000,00eur t b
+
+2021-01-01 -2.00eur t ignored  \n\
+")?;
+        let opts = db::Options::default();
+        let v = db::Entry::read_db_located(&dir.join("db"), &opts).unwrap();
+        let files = db::Entry::read_db_files(&dir.join("db"), &opts).unwrap();
+        let prefix = format!("{}/", dir.display());
+        let warnings = |c: &Config, today| {
+            super::check(&v, &files, &opts, c, date(today)).unwrap()
+        };
+        let msgs = |c: &Config| warnings(c, "2021-01-01")
+            .iter()
+            .map(|x| x.to_string().replace(&prefix, ""))
+            .collect::<Vec<_>>();
+        assert_eq!(msgs(&Config::default()), [
+            concat!(
+                "db/a.txt:2:1: date 2020-04-18 is earlier than 2020-04-19 ",
+                "at db/a.txt:1 [order]"),
+            concat!(
+                r#"db/a.txt:2:12: amount "-2eur" should be "-2.00eur""#,
+                " [canonical-amount]"),
+            "db/a.txt:3:25: trailing whitespace [trailing-whitespace]",
+            "db/a.txt:4:11: consecutive spaces [double-space]",
+            r#"db/a.txt:5:3: uppercase currency "EUR" [uppercase-currency]"#,
+            concat!(
+                "db/b.txt:1:1: date 2020-04-19 is earlier than 2020-04-20 ",
+                "at db/a.txt:6 [order]"),
+            "shared.txt:1:9: trailing whitespace [trailing-whitespace]",
+            concat!(
+                r#"db/d.txt:4:12: amount "-2.000,00eur" should be "#,
+                r#""-2000,00eur" [canonical-amount]"#),
+        ]);
+        let c = super::parse("order off\ncanonical-amount off\n").unwrap();
+        assert_eq!(msgs(&c).len(), 4);
+        let future = warnings(&Config::default(), "2020-12-31")
+            .into_iter()
+            .filter(|x| x.rule == Rule::Future)
+            .count();
+        assert_eq!(future, 4);
+        Ok(())
+    }
+}
@@ -1,26 +1,89 @@
+use std::convert::TryFrom;
+
+mod account;
+mod budget;
 mod cache;
+mod commodity;
 mod db;
 mod dec;
+mod duplicate;
+mod expr;
+mod filter;
+mod iso4217;
+mod lint;
+mod money;
 mod net;
 mod plot;
+mod recurring;
+mod registry;
+mod report;
+mod tag;
 
 const PROG_NAME: &'static str = "nummi";
 
+/// Default window of `check --duplicates`, in days.
+const DUPLICATE_DAYS: i64 = 3;
+
 fn usage() {
     print!(r#"Usage: {exe} [-d <db_dir>] [<cmd>] [<args>]
 
   -d, --db-dir path          path to the database directory
                              (default: $XDG_DATA_HOME/{prog_name}/db)
+  -f, --filter f             only consider entries matching `f`: `#name` for
+                             entries with a hashtag or `key=value` for entries
+                             with a metadata value (may be repeated)
+  -j, --json                 list entries as JSON objects, one per line
+  -m, --decimal-mark c       accept amounts in locale-style notation, with `c`
+                             (`.` or `,`) as the decimal mark and the other one
+                             as thousands separator
+  -r, --rounding mode        rounding mode used for converted values in
+                             reports and checks: half-even, half-up, floor,
+                             ceiling or truncate (default: half-even)
+  -u, --until date           include the entries generated by recurring rules
+                             up to `date` (e.g. `2020-12-31`)
 
 Commands:
 
   <none>                     List all entries.
-  check                      Verify database entries.
+  accounts                   Show the balance of each account.
+  budget                     Compare the budgets of each tag with the actual
+                             expenses in the current and past periods.  Exits
+                             with a non-zero status if any budget is exceeded.
+  check [--double-entry] [--tags] [--duplicates[=<days>]]
+        [--lint|--lint-strict]
+                             Verify database entries, commodities and
+                             balance assertions.
+                             With `--double-entry`, also verify that all
+                             entries have an account and that each
+                             transaction balances to zero.  With `--tags`,
+                             verify that all tags are declared in the tag
+                             definitions file.  With `--duplicates`, report
+                             identical entries and entries with the same
+                             value and similar descriptions at most `days`
+                             (default 3) days apart.  Entries with
+                             `duplicate=ok` are never reported.  With
+                             `--lint`, warn about dates out of order or in
+                             the future and about formatting issues, each
+                             rule can be disabled in the lint configuration
+                             file.  With `--lint-strict`, also exit with a
+                             non-zero status if there are warnings.
   currencies                 List all currencies present in the database.
   update-cache               Force an update of the currency exchange cache
                              file.
-  plot                       Generate a `gnuplot` graphic summarizing with the
-                             monthly historical total.
+  materialize <date>         Print the entries generated by recurring rules up
+                             to `date` which are not in the database yet, to
+                             be added to it as real entries.
+  plot [<tag>]               Generate a `gnuplot` graphic summarizing with the
+                             monthly historical total, optionally only of a
+                             tag and its descendants.
+  report [<group>]           Show the totals of each tag.  `group` can be a
+                             number, to roll tags up to their ancestors with
+                             that many components, `#` to group by hashtag or
+                             `<key>=` to group by the value of a metadata key.
+  split <entry> <ratio>...   Split the value of an entry into several entries
+                             according to ratios (e.g. `1 1 2`) or percentages
+                             (e.g. `50% 30% 20%`).  Each ratio can be followed
+                             by `:<tag>` to change the tag of that entry.
 "#,
         exe = std::env::args().next().unwrap(),
         prog_name = PROG_NAME,
@@ -30,11 +93,21 @@ Commands:
 struct Configuration {
     exe: String,
     dir: std::path::PathBuf,
+    opts: db::Options,
+    filters: Vec<filter::Filter>,
+    rounding: dec::Rounding,
+    until: Option<chrono::NaiveDate>,
+    json: bool,
     args: Vec<String>,
 }
 
 fn parse_args() -> Option<Configuration> {
     let mut dir = std::path::PathBuf::new();
+    let mut opts = db::Options::default();
+    let mut filters = Vec::new();
+    let mut rounding = dec::Rounding::HalfEven;
+    let mut until = None;
+    let mut json = false;
     let mut pos = Vec::new();
     let mut args = std::env::args();
     let exe = args.next().unwrap();
@@ -45,6 +118,24 @@ fn parse_args() -> Option<Configuration> {
                 "-h" | "--help" => { usage(); return None; },
                 "-d" | "--db-dir" => dir = std::path::PathBuf::from(
                     args.next().expect("-d requires an argument")),
+                "-f" | "--filter" => filters.push(args.next()
+                    .expect("-f requires an argument")
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e))),
+                "-j" | "--json" => json = true,
+                "-m" | "--decimal-mark" => opts.format = args.next()
+                    .expect("-m requires an argument")
+                    .parse()
+                    .ok()
+                    .and_then(dec::Format::with_decimal_mark)
+                    .expect("invalid decimal mark"),
+                "-r" | "--rounding" => rounding = args.next()
+                    .expect("-r requires an argument")
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e)),
+                "-u" | "--until" => until = Some(db::Entry::parse_date(
+                        &args.next().expect("-u requires an argument"))
+                    .unwrap_or_else(|e| panic!("{}", e))),
                 _ => pos.push(String::from(arg)),
             },
         }
@@ -59,36 +150,409 @@ fn parse_args() -> Option<Configuration> {
             .join(PROG_NAME)
             .join("db");
     }
-    Some(Configuration { exe, dir, args: pos })
+    Some(Configuration {
+        exe, dir, opts, filters, rounding, until, json, args: pos,
+    })
+}
+
+/// Reads all entries which match `filters`, including those generated by
+/// recurring rules up to `until`, exiting on errors.
+fn read_db(
+    d: &std::path::Path,
+    cmd: &str,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+) -> Vec<db::Entry> {
+    let mut ret = exit_on_db_error(cmd, match until {
+        Some(x) => db::Entry::read_db_until(d, opts, x),
+        None => db::Entry::read_db(d, opts),
+    });
+    ret.retain(|x| filter::Filter::matches_all(filters, x));
+    ret
+}
+
+fn cmd_list(
+    d: &std::path::Path,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+    json: bool,
+) {
+    let tags = read_registry(d, "list");
+    for x in read_db(d, "list", opts, filters, until) {
+        if json {
+            println!("{}", serde_json::to_string(&x).unwrap());
+        } else if let Some(n) = tags.name(&x.tag) {
+            println!("{} ; {}", x.to_line(), n);
+        } else {
+            println!("{}", x.to_line());
+        }
+    }
+}
+
+/// Returns the result of reading the database, exiting on errors.
+fn exit_on_db_error<T>(cmd: &str, r: Result<T, db::DBError>) -> T {
+    r.unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        std::process::exit(1);
+    })
+}
+
+/// Reads the tag definitions of the database, exiting on errors.
+fn read_registry(d: &std::path::Path, cmd: &str) -> registry::Registry {
+    registry::read(&d.join(registry::FILE_NAME)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        std::process::exit(1);
+    })
+}
+
+fn cmd_check(
+    d: &std::path::Path,
+    opts: &db::Options,
+    rounding: dec::Rounding,
+    args: &[String],
+) {
+    let mut double_entry = false;
+    let mut tags = false;
+    let mut duplicates = None;
+    let mut lint = None;
+    for x in args {
+        match x.as_str() {
+            "--double-entry" => double_entry = true,
+            "--tags" => tags = true,
+            "--lint" => lint = Some(false),
+            "--lint-strict" => lint = Some(true),
+            "--duplicates" => duplicates = Some(DUPLICATE_DAYS),
+            _ if x.starts_with("--duplicates=") =>
+                duplicates = Some(x["--duplicates=".len()..].parse()
+                    .ok()
+                    .filter(|x: &i64| *x >= 0)
+                    .unwrap_or_else(|| {
+                        eprintln!("check: invalid number of days: {}", x);
+                        std::process::exit(1);
+                    })),
+            _ => {
+                eprintln!("check: invalid argument: {}", x);
+                std::process::exit(1);
+            },
+        }
+    }
+    let known = commodity::read_known(&d.join(commodity::FILE_NAME))
+        .unwrap_or_else(|e| {
+            eprintln!("check: {}", e);
+            std::process::exit(1);
+        });
+    if let Err(v) = db::Entry::check_db(d, opts, &known) {
+        for e in v {
+            eprintln!("{}", e);
+        }
+        std::process::exit(1);
+    }
+    if let Some(days) = duplicates {
+        if let Err(v) = db::Entry::check_duplicates(d, opts, days) {
+            for e in v {
+                eprintln!("{}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+    if let Some(strict) = lint {
+        let config = lint::read(&d.join(lint::FILE_NAME))
+            .unwrap_or_else(|e| {
+                eprintln!("check: {}", e);
+                std::process::exit(1);
+            });
+        let entries = exit_on_db_error(
+            "check", db::Entry::read_db_located(d, opts));
+        let files =
+            exit_on_db_error("check", db::Entry::read_db_files(d, opts));
+        let today = chrono::Local::now().naive_local().date();
+        let v = lint::check(&entries, &files, opts, &config, today)
+            .unwrap_or_else(|e| {
+                eprintln!("check: {}", e);
+                std::process::exit(1);
+            });
+        for w in &v {
+            eprintln!("{}", w);
+        }
+        if strict && !v.is_empty() {
+            std::process::exit(1);
+        }
+    }
+    if !double_entry && !tags {
+        return;
+    }
+    let entries =
+        exit_on_db_error("check", db::Entry::read_db_located(d, opts));
+    if double_entry {
+        if let Err(v) = db::Entry::check_balanced(&entries, rounding) {
+            for e in v {
+                eprintln!("{}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+    if tags {
+        if let Err(v) = read_registry(d, "check").check(&entries) {
+            for e in v {
+                eprintln!("{}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn cmd_accounts(
+    d: &std::path::Path,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+) {
+    let entries = read_db(d, "accounts", opts, filters, until);
+    let ret = report::balances(entries.iter())
+        .map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, e.to_string()))
+        .and_then(|x| report::write_balances(&mut std::io::stdout(), &x));
+    if let Err(e) = ret {
+        eprintln!("accounts: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn cmd_budget(
+    d: &std::path::Path,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+    rounding: dec::Rounding,
+) {
+    let budgets = budget::read(&d.join(budget::FILE_NAME), opts)
+        .unwrap_or_else(|e| {
+            eprintln!("budget: {}", e);
+            std::process::exit(1);
+        });
+    let tags = read_registry(d, "budget");
+    let entries = read_db(d, "budget", opts, filters, until);
+    let to_eur: std::collections::HashMap<_, _> = update_cache(false)
+        .unwrap_or_else(|e| {
+            eprintln!("budget: failed to read currency cache: {}", e);
+            std::process::exit(1);
+        })
+        .currencies
+        .iter()
+        .map(|x| (x.name, x.to_eur))
+        .collect();
+    let rows = budget::report(
+        &budgets,
+        &entries,
+        &to_eur,
+        chrono::Local::now().naive_local().date(),
+        rounding);
+    let ret = rows
+        .map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, e.to_string()))
+        .and_then(|x| budget::write(
+            &mut std::io::stdout(), &x, |t| tags.label(t)).and(Ok(x)));
+    match ret {
+        Ok(x) if x.iter().any(budget::Row::exceeded) => std::process::exit(1),
+        Ok(_) => {},
+        Err(e) => {
+            eprintln!("budget: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn cmd_currencies(d: &std::path::Path, opts: &db::Options) {
+    let entries =
+        exit_on_db_error("currencies", db::Entry::read_db(d, opts));
+    for x in db::Entry::unique_currencies(&entries) {
+        println!("{}", x);
+    }
 }
 
-fn cmd_list(d: &std::path::Path) {
-    for x in db::Entry::read_db(&d).unwrap() {
+fn cmd_report(
+    d: &std::path::Path,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+    args: &[String],
+) {
+    let group = match args {
+        [] => filter::Group::Tag(None),
+        [x] => x.parse().unwrap_or_else(|e| {
+            eprintln!("report: {}", e);
+            std::process::exit(1);
+        }),
+        _ => {
+            eprintln!("report: too many arguments");
+            std::process::exit(1);
+        },
+    };
+    let tags = read_registry(d, "report");
+    let entries = read_db(d, "report", opts, filters, until);
+    let rows = report::totals(entries.iter(), |x| match group {
+        filter::Group::Tag(_) => group.keys(x)
+            .into_iter()
+            .map(|k| k.parse().map_or(k, |t| tags.label(&t)))
+            .collect(),
+        _ => group.keys(x),
+    });
+    let ret = rows
+        .map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, e.to_string()))
+        .and_then(|x| report::write(&mut std::io::stdout(), &x));
+    if let Err(e) = ret {
+        eprintln!("report: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn cmd_materialize(d: &std::path::Path, opts: &db::Options, args: &[String]) {
+    let until = match args {
+        [x] => db::Entry::parse_date(x).unwrap_or_else(|e| {
+            eprintln!("materialize: {}", e);
+            std::process::exit(1);
+        }),
+        _ => {
+            eprintln!("materialize: a date is required");
+            std::process::exit(1);
+        },
+    };
+    let entries =
+        exit_on_db_error("materialize", db::Entry::read_db(d, opts));
+    let rules = recurring::read(&d.join(recurring::FILE_NAME), opts)
+        .unwrap_or_else(|e| {
+            eprintln!("materialize: {}", e);
+            std::process::exit(1);
+        });
+    for x in recurring::expand(&rules, &entries, until) {
         println!("{}", x.to_line());
     }
 }
 
-fn cmd_check(d: &std::path::Path) {
-    db::Entry::check_db(&d).unwrap();
+fn cmd_split(opts: &db::Options, args: &[String]) {
+    let (line, specs) = match args.split_first() {
+        Some((line, specs)) if !specs.is_empty() => (line, specs),
+        _ => {
+            eprintln!("split: an entry and at least one ratio are required");
+            std::process::exit(1);
+        },
+    };
+    let parts = parse_split_ratios(specs).unwrap_or_else(|e| {
+        eprintln!("split: {}", e);
+        std::process::exit(1);
+    });
+    let entry = db::Entry::from_line_with(line, opts).unwrap_or_else(|e| {
+        eprintln!("split: {}", e);
+        std::process::exit(1);
+    });
+    match entry.split(&parts) {
+        Some(v) => v.iter().for_each(|x| println!("{}", x.to_line())),
+        None => {
+            eprintln!("split: invalid ratios");
+            std::process::exit(1);
+        },
+    }
 }
 
-fn cmd_currencies(d: &std::path::Path) {
-    for x in db::Entry::unique_currencies(&db::Entry::read_db(&d).unwrap()) {
-        println!("{}", std::str::from_utf8(&x).unwrap());
+fn parse_split_ratios(
+    specs: &[String],
+) -> Result<Vec<(dec::Decimal, Option<tag::Tag>)>, String> {
+    let mut ret = Vec::new();
+    let mut percent = 0;
+    for x in specs {
+        let (ratio, tag) = match x.find(':') {
+            Some(i) => (&x[..i], Some(x[i + 1..].parse()?)),
+            None => (x.as_str(), None),
+        };
+        let ratio = match ratio.strip_suffix('%') {
+            Some(r) => { percent += 1; r },
+            None => ratio,
+        };
+        let ratio = dec::Decimal::try_from(ratio)
+            .map_err(|_| format!("invalid ratio: {}", x))?;
+        ret.push((ratio, tag));
+    }
+    if percent != 0 {
+        if percent != ret.len() {
+            return Err(String::from("cannot mix ratios and percentages"));
+        }
+        let total = ret.iter().fold(dec::Decimal::default(), |t, x| t + x.0);
+        if total != dec::Decimal::from(100) {
+            return Err(format!("percentages add up to {}, not 100", total));
+        }
     }
+    Ok(ret)
 }
 
-fn cmd_plot(d: &std::path::Path) {
-    let entries = db::Entry::read_db(&d).unwrap();
-    let currencies = update_cache(false).unwrap().currencies;
+fn cmd_plot(
+    d: &std::path::Path,
+    opts: &db::Options,
+    filters: &[filter::Filter],
+    until: Option<chrono::NaiveDate>,
+    rounding: dec::Rounding,
+    args: &[String],
+) {
+    let tag: Option<tag::Tag> = match args {
+        [] => None,
+        [x] => Some(x.parse().unwrap_or_else(|e| {
+            eprintln!("plot: {}", e);
+            std::process::exit(1);
+        })),
+        _ => {
+            eprintln!("plot: too many arguments");
+            std::process::exit(1);
+        },
+    };
+    let mut entries = read_db(d, "plot", opts, filters, until);
+    let mut style = plot::Style::default();
+    if let Some(t) = &tag {
+        entries.retain(|x| x.tag.is_under(t));
+        let tags = read_registry(d, "plot");
+        style.title = Some(tags.label(t));
+        let def = tags.get(t);
+        if let Some(c) = def.and_then(|x| x.colour.as_ref()) {
+            match def.and_then(|x| x.kind) {
+                Some(registry::Kind::Income) => style.income = c.clone(),
+                Some(registry::Kind::Expense) => style.expense = c.clone(),
+                None => {
+                    style.income = c.clone();
+                    style.expense = c.clone();
+                },
+            }
+        }
+    }
+    if entries.is_empty() {
+        eprintln!("plot: no entries");
+        std::process::exit(1);
+    }
+    let currencies = update_cache(false)
+        .unwrap_or_else(|e| {
+            eprintln!("plot: failed to read currency cache: {}", e);
+            std::process::exit(1);
+        })
+        .currencies;
     let currencies: std::collections::HashMap<_, _> = currencies
         .iter()
-        .map(|x| (x.name, dec::Decimal::new(1.0) / x.to_eur))
+        .map(|x| dec::Decimal::from(1).checked_div(x.to_eur)
+            .map(|r| (x.name, r))
+            .unwrap_or_else(|e| {
+                eprintln!("plot: invalid rate for {}: {}", x.name, e);
+                std::process::exit(1);
+            }))
         .collect();
-    plot::plot(
+    let ret = plot::plot(
         &entries,
         &currencies,
-        &chrono::Local::now().naive_local().date()).unwrap();
+        &chrono::Local::now().naive_local().date(),
+        rounding,
+        &style);
+    if let Err(e) = ret {
+        eprintln!("plot: {}", e);
+        std::process::exit(1);
+    }
 }
 
 fn update_cache(force: bool) -> std::io::Result<cache::Cache> {
@@ -104,11 +568,25 @@ fn main() {
     };
     let mut args = conf.args.iter();
     match args.next().map(|x| x.as_str()).unwrap_or_default() {
-        "" => cmd_list(&conf.dir),
-        "check" => cmd_check(&conf.dir),
-        "currencies" => cmd_currencies(&conf.dir),
+        "" => cmd_list(
+            &conf.dir, &conf.opts, &conf.filters, conf.until, conf.json),
+        "accounts" => cmd_accounts(
+            &conf.dir, &conf.opts, &conf.filters, conf.until),
+        "budget" => cmd_budget(
+            &conf.dir, &conf.opts, &conf.filters, conf.until, conf.rounding),
+        "check" =>
+            cmd_check(&conf.dir, &conf.opts, conf.rounding, args.as_slice()),
+        "currencies" => cmd_currencies(&conf.dir, &conf.opts),
         "update-cache" => update_cache(true).and(Ok(())).unwrap(),
-        "plot" => cmd_plot(&conf.dir),
+        "materialize" => cmd_materialize(
+            &conf.dir, &conf.opts, args.as_slice()),
+        "plot" => cmd_plot(
+            &conf.dir, &conf.opts, &conf.filters, conf.until, conf.rounding,
+            args.as_slice()),
+        "report" => cmd_report(
+            &conf.dir, &conf.opts, &conf.filters, conf.until,
+            args.as_slice()),
+        "split" => cmd_split(&conf.opts, args.as_slice()),
         x => {
             eprintln!("{}: invalid command: {}", conf.exe, x);
             std::process::exit(1);
@@ -0,0 +1,280 @@
+use super::commodity::Commodity;
+use super::dec;
+
+/// An amount in a specific currency (or any other commodity).
+///
+/// Arithmetic is only defined between values in the same currency, values in
+/// different currencies have to be explicitly converted first.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash,
+    serde::Serialize, serde::Deserialize,
+)]
+pub struct Money {
+    pub amount: dec::Decimal,
+    pub currency: Commodity,
+}
+
+/// Explicit conversion of a value to another currency, written `@<rate>` (the
+/// price of one unit) or `@@<total>` (the price of the whole value) after
+/// the amount in database files, e.g. `1000.00eur@@25500czk`.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash,
+    serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Price {
+    Unit(Money),
+    Total(Money),
+}
+
+impl Price {
+    /// Value of `m` in the currency of the price.  The result has the sign
+    /// of `m`.
+    pub fn apply(&self, m: Money) -> Result<Money, Error> {
+        match *self {
+            Price::Unit(r) => m.convert(r.amount, r.currency),
+            Price::Total(t) if m.is_negative() != t.is_negative() => Ok(-t),
+            Price::Total(t) => Ok(t),
+        }
+    }
+}
+
+/// Formats the price as written in database files.  Unit prices are written
+/// exactly, as they are not limited to the minor units of the currency.
+impl std::fmt::Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Price::Unit(x) => write!(f, "@{}{}", x.amount, x.currency),
+            Price::Total(x) => write!(f, "@@{}", x),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    CurrencyMismatch(Commodity, Commodity),
+    MissingRate(Commodity),
+    Decimal(dec::Error),
+}
+
+impl From<dec::Error> for Error {
+    fn from(e: dec::Error) -> Error {
+        Error::Decimal(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::CurrencyMismatch(l, r) =>
+                write!(f, "currency mismatch: {} and {}", l, r),
+            Error::MissingRate(c) => write!(f, "no exchange rate for {}", c),
+            Error::Decimal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Money {
+    pub fn new(amount: dec::Decimal, currency: Commodity) -> Money {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Commodity) -> Money {
+        Money::new(dec::Decimal::default(), currency)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount.is_negative()
+    }
+
+    fn check_currency(&self, o: &Money) -> Result<(), Error> {
+        if self.currency == o.currency {
+            Ok(())
+        } else {
+            Err(Error::CurrencyMismatch(self.currency, o.currency))
+        }
+    }
+
+    pub fn checked_add(self, o: Money) -> Result<Money, Error> {
+        self.check_currency(&o)?;
+        Ok(Money::new(self.amount.checked_add(o.amount)?, self.currency))
+    }
+
+    pub fn checked_sub(self, o: Money) -> Result<Money, Error> {
+        self.check_currency(&o)?;
+        Ok(Money::new(self.amount.checked_sub(o.amount)?, self.currency))
+    }
+
+    /// Converts to currency `to`, where one unit of the current currency is
+    /// worth `rate` units of `to`.
+    pub fn convert(
+        self,
+        rate: dec::Decimal,
+        to: Commodity,
+    ) -> Result<Money, Error> {
+        Ok(Money::new(self.amount.checked_mul(rate)?, to))
+    }
+
+    /// Splits the amount into parts proportional to `ratios`, see
+    /// `dec::Decimal::allocate`.  Parts have the precision of the currency,
+    /// or the scale of the amount for other commodities.
+    pub fn allocate(self, ratios: &[dec::Decimal]) -> Option<Vec<Money>> {
+        let p = self.currency.precision().unwrap_or(self.amount.scale());
+        self.amount.allocate(ratios, p).map(|v| v
+            .into_iter()
+            .map(|x| Money::new(x, self.currency))
+            .collect())
+    }
+
+    /// Rounds the amount to the minor units of the currency.  Amounts in
+    /// other commodities are not changed.
+    pub fn round(self, mode: dec::Rounding) -> Money {
+        match self.currency.precision() {
+            Some(p) => Money::new(self.amount.round(p, mode), self.currency),
+            None => self,
+        }
+    }
+}
+
+impl core::ops::Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Self::Output {
+        Money::new(-self.amount, self.currency)
+    }
+}
+
+/// Formats the amount with the number of decimal places of the currency,
+/// followed by the currency code, e.g. `-100.00eur`.
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.currency.precision() {
+            Some(p) => write!(
+                f, "{:.*}{}", p as usize, self.amount, self.currency),
+            None => write!(f, "{}{}", self.amount, self.currency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Commodity;
+    use super::dec;
+    use super::Error;
+    use super::Money;
+    use super::Price;
+
+    const EUR: Commodity = Commodity::iso(b"eur");
+    const USD: Commodity = Commodity::iso(b"usd");
+    const JPY: Commodity = Commodity::iso(b"jpy");
+
+    #[test]
+    fn arithmetic() {
+        let m = Money::new(dec::Decimal::new(1050, 2), EUR);
+        assert_eq!(
+            m.checked_add(Money::new(dec::Decimal::new(25, 2), EUR)),
+            Ok(Money::new(dec::Decimal::new(1075, 2), EUR)));
+        assert_eq!(
+            m.checked_sub(Money::new(dec::Decimal::from(11), EUR)),
+            Ok(Money::new(dec::Decimal::new(-50, 2), EUR)));
+        assert_eq!(
+            m.checked_add(Money::new(dec::Decimal::from(1), USD)),
+            Err(Error::CurrencyMismatch(EUR, USD)));
+        assert_eq!(
+            m.checked_sub(Money::zero(USD)),
+            Err(Error::CurrencyMismatch(EUR, USD)));
+    }
+
+    #[test]
+    fn convert() {
+        let m = Money::new(dec::Decimal::new(1050, 2), USD);
+        assert_eq!(
+            m.convert(dec::Decimal::new(5, 1), EUR),
+            Ok(Money::new(dec::Decimal::new(525, 2), EUR)));
+        assert_eq!(
+            Money::new(dec::Decimal::new(i128::MAX, 0), USD)
+                .convert(dec::Decimal::from(2), EUR),
+            Err(Error::Decimal(dec::Error::Overflow)));
+    }
+
+    #[test]
+    fn price() {
+        let m = Money::new(dec::Decimal::from(-1000), EUR);
+        let czk = "czk".parse().unwrap();
+        let p = Price::Unit(Money::new(dec::Decimal::new(2555, 2), czk));
+        assert_eq!(p.to_string(), "@25.55czk");
+        assert_eq!(
+            p.apply(m),
+            Ok(Money::new(dec::Decimal::from(-25550), czk)));
+        let p = Price::Total(Money::new(dec::Decimal::from(25500), czk));
+        assert_eq!(p.to_string(), "@@25500.00czk");
+        assert_eq!(
+            p.apply(m),
+            Ok(Money::new(dec::Decimal::from(-25500), czk)));
+        assert_eq!(
+            p.apply(-m),
+            Ok(Money::new(dec::Decimal::from(25500), czk)));
+    }
+
+    #[test]
+    fn round() {
+        let m = Money::new(dec::Decimal::new(10125, 3), EUR);
+        assert_eq!(
+            m.round(dec::Rounding::HalfEven),
+            Money::new(dec::Decimal::new(1012, 2), EUR));
+        assert_eq!(
+            m.round(dec::Rounding::HalfUp),
+            Money::new(dec::Decimal::new(1013, 2), EUR));
+        assert_eq!(
+            Money::new(dec::Decimal::new(10125, 3), JPY)
+                .round(dec::Rounding::Ceiling),
+            Money::new(dec::Decimal::from(11), JPY));
+    }
+
+    #[test]
+    fn allocate() {
+        let ratios = [dec::Decimal::from(1), dec::Decimal::from(2)];
+        assert_eq!(
+            Money::new(dec::Decimal::from(100), EUR).allocate(&ratios),
+            Some(vec![
+                Money::new(dec::Decimal::new(3333, 2), EUR),
+                Money::new(dec::Decimal::new(6667, 2), EUR),
+            ]));
+        assert_eq!(
+            Money::new(dec::Decimal::from(100), JPY).allocate(&ratios),
+            Some(vec![
+                Money::new(dec::Decimal::from(33), JPY),
+                Money::new(dec::Decimal::from(67), JPY),
+            ]));
+        let aapl = "aapl".parse().unwrap();
+        assert_eq!(
+            Money::new(dec::Decimal::new(10, 1), aapl).allocate(&ratios),
+            Some(vec![
+                Money::new(dec::Decimal::new(3, 1), aapl),
+                Money::new(dec::Decimal::new(7, 1), aapl),
+            ]));
+    }
+
+    #[test]
+    fn serde() {
+        let m = Money::new(dec::Decimal::new(-10050, 2), EUR);
+        let s = serde_json::to_string(&m).unwrap();
+        assert_eq!(s, r#"{"amount":"-100.50","currency":"eur"}"#);
+        assert_eq!(serde_json::from_str::<Money>(&s).unwrap(), m);
+        assert!(serde_json::from_str::<Money>(
+            r#"{"amount":"1","currency":"eur1"}"#).is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Money::new(dec::Decimal::from(-100), EUR).to_string(),
+            "-100.00eur");
+        assert_eq!(
+            Money::new(dec::Decimal::from(1500), JPY).to_string(),
+            "1500jpy");
+        assert_eq!(
+            Money::new(dec::Decimal::new(1, 2), Commodity::iso(b"btc"))
+                .to_string(),
+            "0.01btc");
+    }
+}
@@ -1,7 +1,5 @@
-use std::convert::TryFrom;
-
+use super::commodity::Commodity;
 use super::db;
-use super::dec;
 
 const EUR_SERVICE_URL: &'static str =
     "https://www.ecb.europa.eu/stats/eurofxref/eurofxref.zip";
@@ -22,30 +20,24 @@ fn parse_csv<T: std::io::Read>(
     let headers: Vec<String> =
         csv.headers()?.iter().map(String::from).collect();
     let record = csv.records().next().unwrap().unwrap_or_default();
-    Ok(headers
+    headers
         .iter()
         .map(|x| x.trim().to_lowercase())
         .zip(record.iter())
         .filter(|(k, _)| !k.is_empty() && k != "date" )
-        .map(|(k, v)| {
-            let mut k = k.bytes();
-            db::Currency {
-                name: [
-                    k.next().unwrap(),
-                    k.next().unwrap(),
-                    k.next().unwrap(),
-                ],
-                to_eur: dec::Decimal::try_from(v.trim())
-                    .expect("invalid decimal from currency service"),
-            }
-        })
-        .collect())
+        .map(|(k, v)| Ok(db::Currency {
+            name: k.parse::<Commodity>().map_err(|e| std::io::Error::new(
+                std::io::ErrorKind::InvalidData, e))?,
+            to_eur: db::Currency::parse_rate(v.trim())?,
+        }))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::Commodity;
     use super::db;
-    use super::dec;
+    use crate::dec;
 
     #[test]
     fn parse_csv() -> std::io::Result<()> {
@@ -56,38 +48,38 @@ Date, USD, JPY, BGN, CZK, DKK, GBP, HUF, PLN, RON, SEK, CHF, ISK, NOK, HRK, RUB,
         let mut v = super::parse_csv(&mut csv)?;
         v.sort_by(|l, r| l.partial_cmp(&r).unwrap());
         assert_eq!(v, [
-            ([b'a', b'u', b'd'], dec::Decimal::new(1.7266)),
-            ([b'b', b'g', b'n'], dec::Decimal::new(1.9558)),
-            ([b'b', b'r', b'l'], dec::Decimal::new(5.7619)),
-            ([b'c', b'a', b'd'], dec::Decimal::new(1.5393)),
-            ([b'c', b'h', b'f'], dec::Decimal::new(1.0517)),
-            ([b'c', b'n', b'y'], dec::Decimal::new(7.6888)),
-            ([b'c', b'z', b'k'], dec::Decimal::new(27.447)),
-            ([b'd', b'k', b'k'], dec::Decimal::new(7.4582)),
-            ([b'g', b'b', b'p'], dec::Decimal::new(0.88120)),
-            ([b'h', b'k', b'd'], dec::Decimal::new(8.3987)),
-            ([b'h', b'r', b'k'], dec::Decimal::new(7.5700)),
-            ([b'h', b'u', b'f'], dec::Decimal::new(355.02)),
-            ([b'i', b'd', b'r'], dec::Decimal::new(17001.63)),
-            ([b'i', b'l', b's'], dec::Decimal::new(3.8522)),
-            ([b'i', b'n', b'r'], dec::Decimal::new(83.3760)),
-            ([b'i', b's', b'k'], dec::Decimal::new(157.80)),
-            ([b'j', b'p', b'y'], dec::Decimal::new(116.39)),
-            ([b'k', b'r', b'w'], dec::Decimal::new(1335.34)),
-            ([b'm', b'x', b'n'], dec::Decimal::new(26.3957)),
-            ([b'm', b'y', b'r'], dec::Decimal::new(4.7634)),
-            ([b'n', b'o', b'k'], dec::Decimal::new(11.4843)),
-            ([b'n', b'z', b'd'], dec::Decimal::new(1.8181)),
-            ([b'p', b'h', b'p'], dec::Decimal::new(55.096)),
-            ([b'p', b'l', b'n'], dec::Decimal::new(4.5291)),
-            ([b'r', b'o', b'n'], dec::Decimal::new(4.8373)),
-            ([b'r', b'u', b'b'], dec::Decimal::new(83.2936)),
-            ([b's', b'e', b'k'], dec::Decimal::new(10.9543)),
-            ([b's', b'g', b'd'], dec::Decimal::new(1.5510)),
-            ([b't', b'h', b'b'], dec::Decimal::new(35.269)),
-            ([b't', b'r', b'y'], dec::Decimal::new(7.5658)),
-            ([b'u', b's', b'd'], dec::Decimal::new(1.0837)),
-            ([b'z', b'a', b'r'], dec::Decimal::new(20.5853)),
+            (Commodity::iso(b"aud"), dec::Decimal::new(17266, 4)),
+            (Commodity::iso(b"bgn"), dec::Decimal::new(19558, 4)),
+            (Commodity::iso(b"brl"), dec::Decimal::new(57619, 4)),
+            (Commodity::iso(b"cad"), dec::Decimal::new(15393, 4)),
+            (Commodity::iso(b"chf"), dec::Decimal::new(10517, 4)),
+            (Commodity::iso(b"cny"), dec::Decimal::new(76888, 4)),
+            (Commodity::iso(b"czk"), dec::Decimal::new(27447, 3)),
+            (Commodity::iso(b"dkk"), dec::Decimal::new(74582, 4)),
+            (Commodity::iso(b"gbp"), dec::Decimal::new(88120, 5)),
+            (Commodity::iso(b"hkd"), dec::Decimal::new(83987, 4)),
+            (Commodity::iso(b"hrk"), dec::Decimal::new(75700, 4)),
+            (Commodity::iso(b"huf"), dec::Decimal::new(35502, 2)),
+            (Commodity::iso(b"idr"), dec::Decimal::new(1700163, 2)),
+            (Commodity::iso(b"ils"), dec::Decimal::new(38522, 4)),
+            (Commodity::iso(b"inr"), dec::Decimal::new(833760, 4)),
+            (Commodity::iso(b"isk"), dec::Decimal::new(15780, 2)),
+            (Commodity::iso(b"jpy"), dec::Decimal::new(11639, 2)),
+            (Commodity::iso(b"krw"), dec::Decimal::new(133534, 2)),
+            (Commodity::iso(b"mxn"), dec::Decimal::new(263957, 4)),
+            (Commodity::iso(b"myr"), dec::Decimal::new(47634, 4)),
+            (Commodity::iso(b"nok"), dec::Decimal::new(114843, 4)),
+            (Commodity::iso(b"nzd"), dec::Decimal::new(18181, 4)),
+            (Commodity::iso(b"php"), dec::Decimal::new(55096, 3)),
+            (Commodity::iso(b"pln"), dec::Decimal::new(45291, 4)),
+            (Commodity::iso(b"ron"), dec::Decimal::new(48373, 4)),
+            (Commodity::iso(b"rub"), dec::Decimal::new(832936, 4)),
+            (Commodity::iso(b"sek"), dec::Decimal::new(109543, 4)),
+            (Commodity::iso(b"sgd"), dec::Decimal::new(15510, 4)),
+            (Commodity::iso(b"thb"), dec::Decimal::new(35269, 3)),
+            (Commodity::iso(b"try"), dec::Decimal::new(75658, 4)),
+            (Commodity::iso(b"usd"), dec::Decimal::new(10837, 4)),
+            (Commodity::iso(b"zar"), dec::Decimal::new(205853, 4)),
         ]
             .iter()
             .copied()
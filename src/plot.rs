@@ -2,8 +2,12 @@ use std::io::Write;
 
 use chrono::Datelike;
 
+use super::commodity::Commodity;
 use super::db;
 use super::dec;
+use super::money::{self, Money};
+
+const EUR: Commodity = Commodity::iso(b"eur");
 
 struct DateSeries {
     d: chrono::NaiveDate,
@@ -29,40 +33,68 @@ impl Iterator for DateSeries {
     }
 }
 
+/// Title and colours of a plot.
+pub struct Style {
+    pub title: Option<String>,
+    pub income: String,
+    pub expense: String,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            title: None,
+            income: String::from("blue"),
+            expense: String::from("red"),
+        }
+    }
+}
+
 pub fn plot(
     v: &[db::Entry],
-    to_eur: &std::collections::HashMap<[u8; 3], dec::Decimal>,
+    to_eur: &std::collections::HashMap<Commodity, dec::Decimal>,
     end: &chrono::NaiveDate,
+    rounding: dec::Rounding,
+    style: &Style,
 ) -> std::io::Result<()> {
-    plot_data(&gen_data(v, to_eur, end)?)
+    plot_data(&gen_data(v, to_eur, end, rounding)?, style)
 }
 
 fn gen_data(
     v: &[db::Entry],
-    to_eur: &std::collections::HashMap<[u8; 3], dec::Decimal>,
+    to_eur: &std::collections::HashMap<Commodity, dec::Decimal>,
     end: &chrono::NaiveDate,
+    rounding: dec::Rounding,
 ) -> std::io::Result<Vec<u8>> {
     let mut out = Vec::new();
     let series = DateSeries::new(&v[0].date);
-    let mut sum = dec::Decimal::new(0.0);
+    let p = EUR.precision().unwrap_or_default() as usize;
+    let mut sum = Money::zero(EUR);
     for d in series.take_while(|x| x <= &end) {
         // TODO entries are ordered, implement `group_by`
         let filtered = v.iter().filter(|x|
             (x.date.year(), x.date.month()) == (d.year(), d.month()));
-        let (pos, neg) = db::Entry::total_with_conversion(filtered, &to_eur);
-        let net = pos + neg;
-        sum += net;
+        let (pos, neg) = db::Entry::total_with_conversion(
+            filtered, &to_eur, EUR, rounding,
+        ).map_err(invalid_data)?;
+        let net = pos.checked_add(neg).map_err(invalid_data)?;
+        sum = sum.checked_add(net).map_err(invalid_data)?;
         write!(
             &mut out,
-            "{}-{:02} {:.2} {:.2} {:.2} {:.2}\n",
-            d.year(), d.month(), pos, neg, net, sum,
+            "{}-{:02} {:.p$} {:.p$} {:.p$} {:.p$}\n",
+            d.year(), d.month(),
+            pos.amount, neg.amount, net.amount, sum.amount, p = p,
         )?;
     }
     Ok(out)
 }
 
+fn invalid_data(e: money::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
 // TODO adjust width
-fn plot_data(b: &[u8]) -> std::io::Result<()> {
+fn plot_data(b: &[u8], style: &Style) -> std::io::Result<()> {
     let mut cmd = std::process::Command::new("gnuplot")
         .stdin(std::process::Stdio::piped())
         .spawn()?;
@@ -70,6 +102,11 @@ fn plot_data(b: &[u8]) -> std::io::Result<()> {
     stdin.write_all(b"$d <<EOD\n")?;
     stdin.write_all(b)?;
     stdin.write_all(b"EOD\n")?;
+    if let Some(t) = &style.title {
+        writeln!(stdin, "set title \"{}\"", t.replace('"', "\\\""))?;
+    }
+    writeln!(stdin, "c_in = \"{}\"", style.income)?;
+    writeln!(stdin, "c_out = \"{}\"", style.expense)?;
     stdin.write_all(
         br#"
 set term png size 4096,1080
@@ -81,10 +118,10 @@ set timefmt "%Y-%m"
 w = 15 * 24 * 60 * 60
 o(x) = (x + 200 * (x < 0 ? -1 : 1))
 plot \
-	$d using 1:2:(w)     with boxes  lc "blue"        title "in", \
-	$d using 1:(o($2)):2 with labels tc "blue"        notitle, \
-	$d using 1:3:(w)     with boxes  lc "red"         title "out", \
-	$d using 1:(o($3)):3 with labels tc "red"         notitle, \
+	$d using 1:2:(w)     with boxes  lc rgb c_in      title "in", \
+	$d using 1:(o($2)):2 with labels tc rgb c_in      notitle, \
+	$d using 1:3:(w)     with boxes  lc rgb c_out     title "out", \
+	$d using 1:(o($3)):3 with labels tc rgb c_out     notitle, \
 	$d using 1:4         with lines  lc "dark-yellow" title "net", \
 	$d using 1:4:4       with labels tc "dark-yellow" notitle, \
 	$d using 1:5         with lines  lc "dark-green"  title "sum", \
@@ -97,6 +134,7 @@ plot \
 mod tests {
     use super::DateSeries;
 
+    use super::Money;
     use super::db;
     use super::dec;
 
@@ -124,29 +162,34 @@ mod tests {
 
     #[test]
     fn gen_data() -> std::io::Result<()> {
-        const EUR: [u8; 3] = [b'e', b'u', b'r'];
-        const USD: [u8; 3] = [b'u', b's', b'd'];
+        const EUR: super::Commodity = super::EUR;
+        const USD: super::Commodity = super::Commodity::iso(b"usd");
         let entries: Vec<db::Entry> = [
-            (1, 1, dec::Decimal::new(-100.0), EUR),
-            (1, 1, dec::Decimal::new(-200.0), EUR),
-            (1, 2, dec::Decimal::new( 300.0), USD),
-            (2, 1, dec::Decimal::new(-400.0), USD),
-            (3, 1, dec::Decimal::new( 500.0), EUR),
+            (1, 1, dec::Decimal::from(-100), EUR),
+            (1, 1, dec::Decimal::from(-200), EUR),
+            (1, 2, dec::Decimal::from( 300), USD),
+            (2, 1, dec::Decimal::from(-400), USD),
+            (3, 1, dec::Decimal::from( 500), EUR),
         ].iter().map(|&(m, d, v, c)| db::Entry {
             date: chrono::NaiveDate::from_ymd(2020, m, d),
-            value: v,
-            currency: c,
-            tag: b't',
+            value: Money::new(v, c),
+            price: None,
+            account: None,
+            tag: "t".parse().unwrap(),
             text: String::from("description"),
+            hashtags: Vec::new(),
+            meta: std::collections::BTreeMap::new(),
+            transaction: None,
         }).collect();
         let to_eur: std::collections::HashMap<_, _> = [
-            (EUR, dec::Decimal::new(1.0)),
-            (USD, dec::Decimal::new(3.0)),
+            (EUR, dec::Decimal::from(1)),
+            (USD, dec::Decimal::from(3)),
         ].iter().copied().collect();
         let ret = super::gen_data(
             &entries,
             &to_eur,
-            &chrono::NaiveDate::from_ymd(2020, 4, 1))?;
+            &chrono::NaiveDate::from_ymd(2020, 4, 1),
+            dec::Rounding::HalfEven)?;
         assert_eq!(std::str::from_utf8(&ret).unwrap(), "\
 2020-01 900.00 -300.00 600.00 600.00
 2020-02 0.00 -1200.00 -1200.00 -600.00
@@ -0,0 +1,261 @@
+use std::convert::TryFrom;
+
+use chrono::Datelike;
+
+use super::db;
+
+/// Name of the file in the database directory which contains the rules.
+pub const FILE_NAME: &str = "recurring";
+
+/// Unit of the interval between occurrences of a rule.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// Interval between occurrences of a rule, e.g. `1m` or `2w`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Period {
+    pub n: u32,
+    pub unit: Unit,
+}
+
+impl Period {
+    /// Date of occurrence `k` of a schedule starting at `start`.  Months and
+    /// years are counted from `start` so that days are kept even after a
+    /// shorter month (e.g. `01-31`, `02-29`, `03-31`).
+    fn nth(
+        &self,
+        start: chrono::NaiveDate,
+        k: u32,
+    ) -> Option<chrono::NaiveDate> {
+        let n = self.n.checked_mul(k)?;
+        match self.unit {
+            Unit::Day => start.checked_add_signed(
+                chrono::Duration::days(i64::from(n))),
+            Unit::Week => start.checked_add_signed(
+                chrono::Duration::weeks(i64::from(n))),
+            Unit::Month => add_months(start, n),
+            Unit::Year => add_months(start, n.checked_mul(12)?),
+        }
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!(r#"invalid period "{}""#, s);
+        let (n, unit) = s.split_at(s.len().saturating_sub(1));
+        let unit = match unit {
+            "d" => Unit::Day,
+            "w" => Unit::Week,
+            "m" => Unit::Month,
+            "y" => Unit::Year,
+            _ => return Err(err()),
+        };
+        match n.parse::<u32>() {
+            Ok(n) if n != 0 => Ok(Period { n, unit }),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Adds `n` months to a date, using the last day of the month if the day
+/// does not exist in it.
+fn add_months(d: chrono::NaiveDate, n: u32) -> Option<chrono::NaiveDate> {
+    let m = d.month0() + n;
+    let year = d.year().checked_add(i32::try_from(m / 12).ok()?)?;
+    let month = m % 12 + 1;
+    (1..=d.day())
+        .rev()
+        .find_map(|day| chrono::NaiveDate::from_ymd_opt(year, month, day))
+}
+
+/// Template of an entry which is repeated periodically, written
+/// `<start>[..<end>] <period> <amount><currency> [<account>] <tag> <text>`.
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    pub start: chrono::NaiveDate,
+    pub end: Option<chrono::NaiveDate>,
+    pub period: Period,
+    pub template: db::Entry,
+}
+
+impl Rule {
+    /// Parses a rule.  Errors contain the column of the invalid field.
+    pub fn from_line(
+        l: &str,
+        opts: &db::Options,
+    ) -> Result<Rule, db::EntryParseError> {
+        let mut fields = l.splitn(3, ' ');
+        let dates = fields.next().unwrap_or_default();
+        let (start, end) = match dates.find("..") {
+            Some(i) => (&dates[..i], Some((&dates[i + 2..], i + 2))),
+            None => (dates, None),
+        };
+        let start = db::Entry::parse_date(start).map_err(|e| e.field(0))?;
+        let end = end
+            .map(|(s, n)| db::Entry::parse_date(s).map_err(|e| e.field(n)))
+            .transpose()?;
+        let col = dates.len() + 1;
+        let period = fields.next()
+            .ok_or_else(|| String::from("missing period"))
+            .and_then(str::parse)
+            .map_err(|e| db::EntryParseError::new(e).field(col))?;
+        let rest = fields.next().unwrap_or_default();
+        let template = db::Entry::parse_posting(start, rest, opts)
+            .map_err(|e| e.field(l.len() - rest.len()))?;
+        Ok(Rule { start, end, period, template })
+    }
+
+    /// Dates of all occurrences up to `until`, inclusive.
+    pub fn dates(
+        &self,
+        until: chrono::NaiveDate,
+    ) -> impl Iterator<Item = chrono::NaiveDate> + '_ {
+        let until = self.end.map_or(until, |x| x.min(until));
+        (0..)
+            .map_while(move |k| self.period.nth(self.start, k))
+            .take_while(move |&x| x <= until)
+    }
+
+    pub fn entries(
+        &self,
+        until: chrono::NaiveDate,
+    ) -> impl Iterator<Item = db::Entry> + '_ {
+        self.dates(until)
+            .map(move |date| db::Entry { date, ..self.template.clone() })
+    }
+}
+
+/// Reads the rules in a file (see `db::read_config`).
+pub fn read(
+    path: &std::path::Path,
+    opts: &db::Options,
+) -> Result<Vec<Rule>, String> {
+    db::read_config(path, |s| parse(s, opts))
+}
+
+/// Parses rules, one per line.
+fn parse(
+    s: &str,
+    opts: &db::Options,
+) -> Result<Vec<Rule>, (usize, db::EntryParseError)> {
+    let mut ret = Vec::new();
+    db::parse_config(s, |l| {
+        let t = l.trim_start();
+        ret.push(Rule::from_line(t, opts)
+            .map_err(|e| e.offset(l.len() - t.len()))?);
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+/// Entries generated by `rules` up to `until` which are not in `existing`,
+/// sorted by date.
+pub fn expand(
+    rules: &[Rule],
+    existing: &[db::Entry],
+    until: chrono::NaiveDate,
+) -> Vec<db::Entry> {
+    let existing: std::collections::HashSet<_> =
+        existing.iter().map(db::Entry::to_line).collect();
+    let mut ret: Vec<_> = rules.iter()
+        .flat_map(|x| x.entries(until))
+        .filter(|x| !existing.contains(&x.to_line()))
+        .collect();
+    ret.sort_by_key(|x| x.date);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::Period;
+    use super::Rule;
+    use super::Unit;
+
+    fn date(s: &str) -> chrono::NaiveDate {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn rule(l: &str) -> Rule {
+        Rule::from_line(l, &db::Options::default()).unwrap()
+    }
+
+    #[test]
+    fn period() {
+        assert_eq!("1m".parse(), Ok(Period { n: 1, unit: Unit::Month }));
+        assert_eq!("2w".parse(), Ok(Period { n: 2, unit: Unit::Week }));
+        for x in &["", "m", "0m", "-1m", "1x", "1.5d"] {
+            assert!(x.parse::<Period>().is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn dates() {
+        let r = rule("2020-01-31 1m -500.00eur rent monthly rent");
+        assert_eq!(r.dates(date("2020-05-01")).collect::<Vec<_>>(), [
+            date("2020-01-31"), date("2020-02-29"), date("2020-03-31"),
+            date("2020-04-30"),
+        ]);
+        let r = rule("2020-01-01..2020-01-29 2w 10.00eur t x");
+        assert_eq!(r.dates(date("2021-01-01")).collect::<Vec<_>>(), [
+            date("2020-01-01"), date("2020-01-15"), date("2020-01-29"),
+        ]);
+        let r = rule("2020-02-29 1y 1.00eur t x");
+        assert_eq!(r.dates(date("2022-03-01")).collect::<Vec<_>>(), [
+            date("2020-02-29"), date("2021-02-28"), date("2022-02-28"),
+        ]);
+        assert_eq!(r.dates(date("2020-01-01")).count(), 0);
+    }
+
+    #[test]
+    fn parse() {
+        let v = super::parse("\
+# rent
+2020-01-01 1m -500.00eur [checking] rent monthly rent
+
+2020-01-15..2020-12-31 2w 1000.00eur salary
+", &db::Options::default()).unwrap();
+        assert_eq!(v.len(), 2);
+        assert_eq!(
+            v[0].template.to_line(),
+            "2020-01-01 -500.00eur [checking] rent monthly rent");
+        assert_eq!(v[1].end, Some(date("2020-12-31")));
+        let err = |s: &str| {
+            let (n, e) = super::parse(s, &db::Options::default())
+                .unwrap_err();
+            (n, e.col(), String::from(e.msg()))
+        };
+        assert_eq!(
+            err("2020-01-01 1m t\n"),
+            (1, Some(15), String::from(r#"invalid amount "t""#)));
+        assert_eq!(
+            err("\n2020-01-01 1x 1.00eur t\n"),
+            (2, Some(12), String::from(r#"invalid period "1x""#)));
+        assert_eq!(err("2020-01-01..2020-13-01 1m 1.00eur t\n").1, Some(13));
+        assert_eq!(err("2020-01-01\n").2, "missing period");
+        assert_eq!(err("  \n 2020-01-01 1x 1.00eur t \n").1, Some(13));
+    }
+
+    #[test]
+    fn expand() {
+        let rules = [
+            rule("2020-01-01 1m -500.00eur rent"),
+            rule("2020-01-15 1m 1000.00eur salary"),
+        ];
+        let existing = [
+            db::Entry::from_line("2020-01-01 -500.00eur rent").unwrap(),
+        ];
+        let v = super::expand(&rules, &existing, date("2020-02-10"));
+        assert_eq!(v.iter().map(db::Entry::to_line).collect::<Vec<_>>(), [
+            "2020-01-15 1000.00eur salary",
+            "2020-02-01 -500.00eur rent",
+        ]);
+    }
+}
@@ -0,0 +1,238 @@
+use super::db;
+use super::tag::Tag;
+
+/// Name of the file in the database directory which contains the tag
+/// definitions.
+pub const FILE_NAME: &str = "tags";
+
+/// Whether a tag is used for income or for expenses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Income,
+    Expense,
+}
+
+impl std::str::FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "income" => Ok(Kind::Income),
+            "expense" => Ok(Kind::Expense),
+            _ => Err(format!(r#"invalid kind "{}""#, s)),
+        }
+    }
+}
+
+/// Information about a tag, all fields are optional.
+#[derive(Debug, Default, PartialEq)]
+pub struct Definition {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub kind: Option<Kind>,
+    /// Display colour, in `#rrggbb` notation.
+    pub colour: Option<String>,
+}
+
+impl Definition {
+    /// Sets an attribute from a `<key> <value>` line.
+    fn set(&mut self, l: &str) -> Result<(), String> {
+        let mut fields = l.splitn(2, ' ');
+        let key = fields.next().unwrap_or_default();
+        let value = fields.next().map(str::trim).unwrap_or_default();
+        if value.is_empty() {
+            return Err(format!(r#"missing value for "{}""#, key));
+        }
+        match key {
+            "name" => self.name = Some(String::from(value)),
+            "description" => self.description = Some(String::from(value)),
+            "kind" => self.kind = Some(value.parse()?),
+            "colour" => {
+                let valid = value.len() == 7
+                    && value.starts_with('#')
+                    && value[1..].bytes().all(|x| x.is_ascii_hexdigit());
+                if !valid {
+                    return Err(format!(r#"invalid colour "{}""#, value));
+                }
+                self.colour = Some(String::from(value));
+            },
+            _ => return Err(format!(r#"invalid attribute "{}""#, key)),
+        }
+        Ok(())
+    }
+}
+
+/// Declared tags, read from a file where each tag is followed by indented
+/// attributes:
+///
+/// ```text
+/// t
+///   name Transport
+///   kind expense
+///   colour #1f77b4
+/// ```
+#[derive(Debug, Default)]
+pub struct Registry {
+    tags: std::collections::BTreeMap<Tag, Definition>,
+}
+
+impl Registry {
+    /// Definition of a tag or, if it is not declared, of its nearest
+    /// declared ancestor.
+    pub fn get(&self, t: &Tag) -> Option<&Definition> {
+        (1..=t.depth()).rev().find_map(|n| self.tags.get(&t.truncate(n)))
+    }
+
+    /// Name of a tag, only if it is declared itself.
+    pub fn name(&self, t: &Tag) -> Option<&str> {
+        self.tags.get(t).and_then(|x| x.name.as_deref())
+    }
+
+    /// The tag followed by its name, e.g. `t (Transport)`.
+    pub fn label(&self, t: &Tag) -> String {
+        match self.name(t) {
+            Some(n) => format!("{} ({})", t, n),
+            None => t.to_string(),
+        }
+    }
+
+    /// Verifies that the tag of each entry or one of its ancestors is
+    /// declared.  Returns an error at the tag of every entry which fails.
+    pub fn check(
+        &self,
+        v: &[(db::Entry, db::Location)],
+    ) -> Result<(), Vec<db::DBError>> {
+        let ret: Vec<_> = v.iter()
+            .filter(|(x, _)| self.get(&x.tag).is_none())
+            .map(|(x, loc)| db::DBError {
+                path: Some(loc.path.clone()),
+                line: Some(loc.line),
+                col: Some(loc.col),
+                kind: db::ErrorKind::Tag(format!(
+                    r#"undeclared tag "{}": {}"#, x.tag, x.to_line())),
+            })
+            .collect();
+        if ret.is_empty() {
+            Ok(())
+        } else {
+            Err(ret)
+        }
+    }
+}
+
+/// Reads the tag definitions in a file (see `db::read_config`).
+pub fn read(path: &std::path::Path) -> Result<Registry, String> {
+    db::read_config(path, parse)
+}
+
+/// Parses tag definitions.
+fn parse(s: &str) -> Result<Registry, (usize, db::EntryParseError)> {
+    let mut ret = Registry::default();
+    let mut cur = None;
+    db::parse_config(s, |l| {
+        let err = db::EntryParseError::new;
+        let indented = l.starts_with(char::is_whitespace);
+        let l = l.trim_start();
+        if indented {
+            let t = cur.as_ref().ok_or_else(||
+                err(String::from("attribute without a tag")))?;
+            return ret.tags.get_mut(t).unwrap().set(l).map_err(err);
+        }
+        let t: Tag = l.parse().map_err(err)?;
+        if ret.tags.contains_key(&t) {
+            return Err(err(format!(r#"duplicate tag "{}""#, t)));
+        }
+        ret.tags.insert(t.clone(), Definition::default());
+        cur = Some(t);
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+    use super::Kind;
+
+    const TAGS: &str = "\
+# transport
+t
+  name Transport
+  description Public transport and taxis
+  kind expense
+  colour #1f77b4
+
+food
+  name Food ; groceries and restaurants
+food:out
+salary
+  kind income
+";
+
+    #[test]
+    fn parse() {
+        let r = super::parse(TAGS).unwrap();
+        let d = r.get(&"t".parse().unwrap()).unwrap();
+        assert_eq!(d.name.as_deref(), Some("Transport"));
+        assert_eq!(
+            d.description.as_deref(), Some("Public transport and taxis"));
+        assert_eq!(d.kind, Some(Kind::Expense));
+        assert_eq!(d.colour.as_deref(), Some("#1f77b4"));
+        let d = r.get(&"salary".parse().unwrap()).unwrap();
+        assert_eq!(d.kind, Some(Kind::Income));
+        assert_eq!(d.name, None);
+        for (s, l) in &[
+            ("  name x\n", 1),
+            ("t\n  name\n", 2),
+            ("t\n  kind other\n", 2),
+            ("t\n  colour blue\n", 2),
+            ("t\n  colour #12345g\n", 2),
+            ("t\n  size 1\n", 2),
+            ("t\nt\n", 2),
+            ("a b\n", 1),
+        ] {
+            assert_eq!(super::parse(s).unwrap_err().0, *l, "{}", s);
+        }
+    }
+
+    #[test]
+    fn names() {
+        let r = super::parse(TAGS).unwrap();
+        let t = |s: &str| s.parse().unwrap();
+        assert_eq!(r.label(&t("t")), "t (Transport)");
+        assert_eq!(r.label(&t("food")), "food (Food)");
+        assert_eq!(r.label(&t("food:out")), "food:out");
+        assert_eq!(r.label(&t("food:groceries")), "food:groceries");
+        assert_eq!(r.label(&t("x")), "x");
+        assert!(r.get(&t("food:groceries")).is_some());
+        assert!(r.get(&t("foo")).is_none());
+    }
+
+    #[test]
+    fn check() {
+        let r = super::parse(TAGS).unwrap();
+        let read = |v: &[&str]| v.iter()
+            .enumerate()
+            .map(|(i, x)| (
+                db::Entry::from_line(x).unwrap(),
+                db::Location { path: "a.txt".into(), line: i + 1, col: 21 }))
+            .collect::<Vec<_>>();
+        assert!(r.check(&read(&[
+            "2020-04-20 -2.00eur t bus",
+            "2020-04-20 -20.00eur food:groceries supermarket",
+        ])).is_ok());
+        let v = r.check(&read(&[
+            "2020-04-20 -2.00eur a bus",
+            "2020-04-20 -2.00eur t bus",
+            "2020-04-21 -5.00eur b:c x",
+        ])).unwrap_err();
+        assert_eq!(v.iter().map(db::DBError::to_string).collect::<Vec<_>>(), [
+            concat!(
+                r#"a.txt:1:21: undeclared tag "a": "#,
+                "2020-04-20 -2.00eur a bus"),
+            concat!(
+                r#"a.txt:3:21: undeclared tag "b:c": "#,
+                "2020-04-21 -5.00eur b:c x"),
+        ]);
+    }
+}
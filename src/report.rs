@@ -0,0 +1,150 @@
+use std::io::Write;
+
+use super::db;
+use super::money::{self, Money};
+
+/// Totals of a group of entries in one currency.
+#[derive(Debug, PartialEq)]
+pub struct Row<K> {
+    pub key: K,
+    pub pos: Money,
+    pub neg: Money,
+    pub net: Money,
+}
+
+/// Groups entries by `keys` and calculates the totals of each group, one row
+/// per currency.  An entry is part of every group returned by `keys`.  Rows
+/// are sorted by key and currency.
+pub fn totals<'a, K: Ord + Clone>(
+    it: impl Iterator<Item = &'a db::Entry>,
+    keys: impl Fn(&db::Entry) -> Vec<K>,
+) -> Result<Vec<Row<K>>, money::Error> {
+    let mut groups = std::collections::BTreeMap::<K, Vec<&db::Entry>>::new();
+    for x in it {
+        for k in keys(x) {
+            groups.entry(k).or_default().push(x);
+        }
+    }
+    let mut ret = Vec::new();
+    for (k, v) in groups {
+        let mut total = db::Entry::total(v.into_iter())?;
+        total.sort_by_key(|x| x.0.currency);
+        for (pos, neg) in total {
+            let net = pos.checked_add(neg)?;
+            ret.push(Row { key: k.clone(), pos, neg, net });
+        }
+    }
+    Ok(ret)
+}
+
+/// Calculates the balance of each account in each currency from the postings
+/// of the entries, sorted by account and currency.
+pub fn balances<'a>(
+    it: impl Iterator<Item = &'a db::Entry>,
+) -> Result<Vec<(String, Money)>, money::Error> {
+    let mut ret = std::collections::BTreeMap::new();
+    for x in it {
+        for (a, m) in x.postings()? {
+            let b = ret
+                .entry((a, m.currency))
+                .or_insert_with(|| Money::zero(m.currency));
+            *b = b.checked_add(m)?;
+        }
+    }
+    Ok(ret.into_iter().map(|((a, _), m)| (String::from(a), m)).collect())
+}
+
+/// Writes account balances as aligned columns.
+pub fn write_balances(
+    out: &mut impl Write,
+    v: &[(String, Money)],
+) -> std::io::Result<()> {
+    let values: Vec<_> = v.iter().map(|x| x.1.to_string()).collect();
+    let kw = v.iter().map(|x| x.0.len()).max().unwrap_or_default();
+    let vw = values.iter().map(String::len).max().unwrap_or_default();
+    for ((k, _), m) in v.iter().zip(values.iter()) {
+        writeln!(out, "{:kw$} {:>vw$}", k, m, kw = kw, vw = vw)?;
+    }
+    Ok(())
+}
+
+/// Writes rows as aligned columns: key, income, expense and net total.
+pub fn write<K: std::fmt::Display>(
+    out: &mut impl Write,
+    v: &[Row<K>],
+) -> std::io::Result<()> {
+    let keys: Vec<_> = v.iter().map(|x| x.key.to_string()).collect();
+    let values: Vec<_> = v
+        .iter()
+        .map(|x| [x.pos.to_string(), x.neg.to_string(), x.net.to_string()])
+        .collect();
+    let kw = keys.iter().map(String::len).max().unwrap_or_default();
+    let vw = values
+        .iter()
+        .flatten()
+        .map(String::len)
+        .max()
+        .unwrap_or_default();
+    for (k, [pos, neg, net]) in keys.iter().zip(values.iter()) {
+        writeln!(out, "{:kw$} {:>vw$} {:>vw$} {:>vw$}",
+            k, pos, neg, net, kw = kw, vw = vw)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db;
+
+    #[test]
+    fn totals() -> std::io::Result<()> {
+        let entries: Vec<db::Entry> = [
+            "2020-04-20 -10.00eur food:groceries a",
+            "2020-04-20 -20.00eur food:restaurants b",
+            "2020-04-20 -1000jpy food:restaurants c",
+            "2020-04-20 5.50eur food:groceries refund",
+            "2020-04-20 100.00eur salary d",
+        ].iter().map(|x| db::Entry::from_line(x).unwrap()).collect();
+        let by_tag = super::totals(entries.iter(), |x| vec![x.tag.clone()])
+            .unwrap();
+        let mut out = Vec::new();
+        super::write(&mut out, &by_tag)?;
+        assert_eq!(std::str::from_utf8(&out).unwrap(), "\
+food:groceries     5.50eur -10.00eur  -4.50eur
+food:restaurants   0.00eur -20.00eur -20.00eur
+food:restaurants      0jpy  -1000jpy  -1000jpy
+salary           100.00eur   0.00eur 100.00eur
+");
+        let by_top =
+            super::totals(entries.iter(), |x| vec![x.tag.truncate(1)])
+                .unwrap();
+        let mut out = Vec::new();
+        super::write(&mut out, &by_top)?;
+        assert_eq!(std::str::from_utf8(&out).unwrap(), "\
+food     5.50eur -30.00eur -24.50eur
+food        0jpy  -1000jpy  -1000jpy
+salary 100.00eur   0.00eur 100.00eur
+");
+        Ok(())
+    }
+
+    #[test]
+    fn balances() -> std::io::Result<()> {
+        let entries: Vec<db::Entry> = [
+            "2020-04-20 1000.00eur [checking] salary a",
+            "2020-04-20 -20.00eur [checking] food b",
+            "2020-04-21 500.00eur [checking>savings] transfer c",
+            "2020-04-21 -5000jpy [cash] food d",
+            "2020-04-22 -10.00eur t no account",
+        ].iter().map(|x| db::Entry::from_line(x).unwrap()).collect();
+        let v = super::balances(entries.iter()).unwrap();
+        let mut out = Vec::new();
+        super::write_balances(&mut out, &v)?;
+        assert_eq!(std::str::from_utf8(&out).unwrap(), "\
+cash      -5000jpy
+checking 480.00eur
+savings  500.00eur
+");
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+/// Hierarchical category, e.g. `food:groceries`.
+///
+/// Components are separated by `:`, a single-component tag (such as the
+/// traditional single-character tags) is a top-level category.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+    serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct Tag(String);
+
+impl Tag {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn components(&self) -> std::str::Split<'_, char> {
+        self.0.split(':')
+    }
+
+    pub fn depth(&self) -> usize {
+        self.components().count()
+    }
+
+    /// Ancestor of this tag with at most `depth` components.
+    pub fn truncate(&self, depth: usize) -> Tag {
+        let n = self.components()
+            .take(depth.max(1))
+            .map(|x| x.len() + 1)
+            .sum::<usize>() - 1;
+        Tag(String::from(&self.0[..n]))
+    }
+
+    /// Whether this tag is `o` or one of its descendants.
+    pub fn is_under(&self, o: &Tag) -> bool {
+        self.0.strip_prefix(&o.0)
+            .is_some_and(|x| x.is_empty() || x.starts_with(':'))
+    }
+}
+
+impl std::str::FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.split(':').any(str::is_empty)
+            || s.contains(char::is_whitespace)
+        {
+            return Err(format!(r#"invalid tag "{}""#, s));
+        }
+        Ok(Tag(String::from(s)))
+    }
+}
+
+impl std::convert::TryFrom<String> for Tag {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Tag> for String {
+    fn from(t: Tag) -> String {
+        t.0
+    }
+}
+
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tag;
+
+    fn t(s: &str) -> Tag {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(t("t").as_str(), "t");
+        assert_eq!(t("food:groceries").depth(), 2);
+        for x in &["", ":", "food:", ":food", "food::x", "a b"] {
+            assert!(x.parse::<Tag>().is_err(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn truncate() {
+        let x = t("food:groceries:fruit");
+        assert_eq!(x.truncate(0), t("food"));
+        assert_eq!(x.truncate(1), t("food"));
+        assert_eq!(x.truncate(2), t("food:groceries"));
+        assert_eq!(x.truncate(3), x);
+        assert_eq!(x.truncate(10), x);
+    }
+
+    #[test]
+    fn is_under() {
+        let x = t("food:groceries");
+        assert!(x.is_under(&t("food")));
+        assert!(x.is_under(&t("food:groceries")));
+        assert!(!x.is_under(&t("foo")));
+        assert!(!x.is_under(&t("food:groceries:fruit")));
+        assert!(!t("t").is_under(&x));
+    }
+}